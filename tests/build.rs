@@ -1,3 +1,13 @@
+use std::env;
+
 fn main() {
-    witnesscalc_adapter::build_and_link("./testdata");
+    let target = env::var("TARGET").unwrap();
+    let mut build = witnesscalc_adapter::Build::new();
+    build.circuits_dir("./testdata");
+    // Also links the baseline field arithmetic for native x86_64 builds, for the CPU dispatch
+    // tests, leaving the cross builds for iOS and Android as they are
+    if target.starts_with("x86_64-") && env::var("HOST").unwrap() == target {
+        build.cpu_dispatch(true);
+    }
+    build.compile().expect("Failed to build circuits");
 }
@@ -4,13 +4,25 @@ mod test {
     use std::collections::HashMap;
 
     use num_bigint::BigInt;
-    use witnesscalc_adapter::{convert_inputs_to_json, parse_witness_to_bigints};
+    use witnesscalc_adapter::{
+        convert_inputs_to_json, detected_field_arithmetic, parse_witness_to_bigints, Circuit,
+        CircuitData, DatSource, FieldArithmetic, WitnessCalculator,
+    };
 
     witnesscalc_adapter::witness!(multiplier2);
     witnesscalc_adapter::witness!(keccak256_256_test);
     witnesscalc_adapter::witness!(rsa_main);
     witnesscalc_adapter::witness!(sha256);
 
+    mod runtime_dat {
+        witnesscalc_adapter::witness!(multiplier2, dat = runtime);
+    }
+
+    mod compressed_dat {
+        witnesscalc_adapter::witness!(multiplier2, dat = compressed);
+        witnesscalc_adapter::witness!(sha256, dat = compressed);
+    }
+
     #[test]
     fn test_witnesscalc() {
         let mut inputs = HashMap::new();
@@ -34,6 +46,184 @@ mod test {
         assert_eq!(witness[3], BigInt::from(3u8));
     }
 
+    #[test]
+    fn test_runtime_dat_witnesscalc() {
+        let json_input = "{\"a\": [\"2\"], \"b\": [\"3\"]}";
+
+        let circuit_data = CircuitData::from_path("testdata/multiplier2.dat").unwrap();
+        let witness_bytes =
+            runtime_dat::multiplier2_witness_with_dat(&circuit_data, json_input).unwrap();
+        assert_eq!(witness_bytes, multiplier2_witness(json_input).unwrap());
+
+        assert!(runtime_dat::multiplier2_witness(json_input).is_err());
+        runtime_dat::multiplier2_load_dat(circuit_data).unwrap();
+        assert_eq!(
+            runtime_dat::multiplier2_witness(json_input).unwrap(),
+            witness_bytes
+        );
+    }
+
+    #[test]
+    fn test_runtime_dat_checksum_mismatch() {
+        let circuit_data = CircuitData::from_path("testdata/sha256.dat").unwrap();
+        assert!(runtime_dat::multiplier2_load_dat(circuit_data).is_err());
+    }
+
+    #[test]
+    fn test_compressed_dat_witnesscalc() {
+        let json_input = "{\"a\": [\"2\"], \"b\": [\"3\"]}";
+        assert_eq!(
+            compressed_dat::multiplier2_witness(json_input).unwrap(),
+            multiplier2_witness(json_input).unwrap()
+        );
+
+        let mut inputs = HashMap::new();
+        inputs.insert("in".to_string(), vec![0u8.to_string(); 512]);
+        let json_input = convert_inputs_to_json(inputs);
+        let witness_bytes = compressed_dat::sha256_witness(&json_input).unwrap();
+        assert_eq!(witness_bytes, sha256_witness(&json_input).unwrap());
+        // The second call reuses the decompressed circuit data
+        assert_eq!(
+            compressed_dat::sha256_witness(&json_input).unwrap(),
+            witness_bytes
+        );
+    }
+
+    fn multiply<C: WitnessCalculator>(a: u8, b: u8) -> Vec<BigInt> {
+        let mut inputs = HashMap::new();
+        inputs.insert("a".to_string(), vec![a.to_string()]);
+        inputs.insert("b".to_string(), vec![b.to_string()]);
+        let witness_bytes = C::calculate_from_inputs(inputs).unwrap();
+        parse_witness_to_bigints(&witness_bytes).unwrap()
+    }
+
+    #[test]
+    fn test_witness_calculator_trait() {
+        assert_eq!(Multiplier2Circuit::NAME, "multiplier2");
+        assert_eq!(Multiplier2Circuit::witness_size(), 4);
+        assert_eq!(Multiplier2Circuit::input_signal_count(), 2);
+        assert_eq!(Sha256Circuit::METADATA.witness_size, 62417);
+        assert_eq!(
+            Multiplier2Circuit::circuit_data().unwrap(),
+            std::fs::read("testdata/multiplier2.dat").unwrap()
+        );
+
+        let witness = multiply::<Multiplier2Circuit>(2, 3);
+        assert_eq!(witness.len(), Multiplier2Circuit::witness_size());
+        assert_eq!(witness[1], BigInt::from(6u8));
+
+        let circuits: Vec<Circuit> = vec![Multiplier2Circuit::circuit(), Sha256Circuit::circuit()];
+        assert_eq!(circuits[1].name(), "sha256");
+        let witness = circuits[0]
+            .calculate_bigints("{\"a\": [\"4\"], \"b\": [\"5\"]}")
+            .unwrap();
+        assert_eq!(witness[1], BigInt::from(20u8));
+    }
+
+    #[test]
+    fn test_registry() {
+        let registry = witnesscalc_adapter::registry();
+        let names: Vec<&str> = registry.names().collect();
+        assert_eq!(
+            names,
+            vec!["keccak256_256_test", "multiplier2", "rsa_main", "sha256"]
+        );
+
+        let circuit = registry.get("multiplier2").unwrap();
+        assert_eq!(circuit.metadata().witness_size, 4);
+        let witness = circuit
+            .calculate_bigints("{\"a\": [\"2\"], \"b\": [\"3\"]}")
+            .unwrap();
+        assert_eq!(witness[1], BigInt::from(6u8));
+
+        assert!(registry.get("unknown").is_err());
+    }
+
+    #[test]
+    fn test_registry_prefers_embedded_dat() {
+        // multiplier2 is also declared with `dat = runtime` and `dat = compressed`, and sha256
+        // with `dat = compressed`
+        let registry = witnesscalc_adapter::registry();
+        for name in ["multiplier2", "sha256"] {
+            assert_eq!(
+                registry.get(name).unwrap().dat_source(),
+                DatSource::Embedded
+            );
+        }
+        assert_eq!(registry.iter().count(), 4);
+    }
+
+    #[test]
+    fn test_build_output_json() {
+        let output: serde_json::Value = serde_json::from_str(include_str!(concat!(
+            env!("OUT_DIR"),
+            "/witnesscalc-build.json"
+        )))
+        .unwrap();
+        let circuits = output["circuits"].as_array().unwrap();
+        assert_eq!(circuits.len(), 4);
+
+        let multiplier2 = circuits
+            .iter()
+            .find(|circuit| circuit["name"] == "multiplier2")
+            .unwrap();
+        assert_eq!(
+            multiplier2["metadata"]["witness_size"],
+            Multiplier2Circuit::METADATA.witness_size
+        );
+        assert!(multiplier2["runtime_version"]
+            .as_str()
+            .unwrap()
+            .starts_with('v'));
+        assert!(std::path::Path::new(multiplier2["lib_path"].as_str().unwrap()).exists());
+        assert_eq!(multiplier2["dat_sha256"].as_str().unwrap().len(), 64);
+    }
+
+    #[test]
+    fn test_two_runtimes() {
+        // multiplier2 is built with the v2.1.0 runtime and sha256 with v2.2.0, whose symbols
+        // are prefixed to link both into this binary
+        let output: serde_json::Value = serde_json::from_str(include_str!(concat!(
+            env!("OUT_DIR"),
+            "/witnesscalc-build.json"
+        )))
+        .unwrap();
+        let runtime_version = |name: &str| {
+            output["circuits"]
+                .as_array()
+                .unwrap()
+                .iter()
+                .find(|circuit| circuit["name"] == name)
+                .unwrap()["runtime_version"]
+                .clone()
+        };
+        assert_eq!(runtime_version("multiplier2"), "v2.1.0");
+        assert_eq!(runtime_version("sha256"), "v2.2.0");
+
+        let witness = parse_witness_to_bigints(
+            &multiplier2_witness("{\"a\": [\"2\"], \"b\": [\"3\"]}").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(witness[1], BigInt::from(6u8));
+        let mut inputs = HashMap::new();
+        inputs.insert("in".to_string(), vec![0u8.to_string(); 512]);
+        let witness =
+            parse_witness_to_bigints(&sha256_witness(&convert_inputs_to_json(inputs)).unwrap())
+                .unwrap();
+        assert_eq!(witness.len(), Sha256Circuit::witness_size());
+    }
+
+    #[test]
+    #[cfg(target_arch = "x86_64")]
+    fn test_detected_field_arithmetic() {
+        let optimized =
+            std::is_x86_feature_detected!("adx") && std::is_x86_feature_detected!("bmi2");
+        assert_eq!(
+            detected_field_arithmetic() == FieldArithmetic::Optimized,
+            optimized
+        );
+    }
+
     #[test]
     fn test_keccak256_256_test_witnesscalc() {
         let mut inputs = HashMap::new();
@@ -0,0 +1,56 @@
+//! The field arithmetic is a process-wide setting, so the tests forcing it run in their own
+//! binary rather than alongside the other witness tests.
+
+use std::collections::HashMap;
+
+use num_bigint::BigInt;
+// Links the circuit libraries built by the build script
+use tests as _;
+use witnesscalc_adapter::{
+    convert_inputs_to_json, detected_field_arithmetic, field_arithmetic, parse_witness_to_bigints,
+    set_field_arithmetic, FieldArithmetic, WitnessCalculator,
+};
+
+witnesscalc_adapter::witness!(multiplier2);
+witnesscalc_adapter::witness!(sha256);
+
+/// Restores the field arithmetic in place when created, even if the test panics.
+struct RestoreFieldArithmetic(Option<FieldArithmetic>);
+
+impl RestoreFieldArithmetic {
+    fn new() -> Self {
+        // Forcing the detected variant behaves as not forcing any
+        let current = field_arithmetic();
+        Self((current != detected_field_arithmetic()).then_some(current))
+    }
+}
+
+impl Drop for RestoreFieldArithmetic {
+    fn drop(&mut self) {
+        set_field_arithmetic(self.0);
+    }
+}
+
+#[test]
+fn test_field_arithmetic_dispatch() {
+    let _restore = RestoreFieldArithmetic::new();
+    let json_input = "{\"a\": [\"2\"], \"b\": [\"3\"]}";
+    let mut variants = vec![FieldArithmetic::Baseline];
+    // The optimised variant crashes on CPUs without ADX and BMI2
+    if detected_field_arithmetic() == FieldArithmetic::Optimized {
+        variants.push(FieldArithmetic::Optimized);
+    }
+    for variant in variants {
+        set_field_arithmetic(Some(variant));
+        assert_eq!(field_arithmetic(), variant);
+        let witness = parse_witness_to_bigints(&multiplier2_witness(json_input).unwrap()).unwrap();
+        assert_eq!(witness[1], BigInt::from(6u8));
+        let witness = Sha256Circuit::calculate(&convert_inputs_to_json(HashMap::from([(
+            "in".to_string(),
+            vec![0u8.to_string(); 512],
+        )])));
+        assert!(witness.is_ok());
+    }
+    set_field_arithmetic(None);
+    assert_eq!(field_arithmetic(), detected_field_arithmetic());
+}
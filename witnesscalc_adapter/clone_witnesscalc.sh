@@ -3,12 +3,15 @@
 # Exit on error
 set -e
 
-# OUT_DIR is specified by the rust build environment
-if [ -z $OUT_DIR ]; then
-    echo "OUT_DIR not specified"
-    exit 1
+# BUILD_DIR defaults to the witnesscalc directory in OUT_DIR, specified by the rust build environment
+if [ -z $BUILD_DIR ]; then
+    if [ -z $OUT_DIR ]; then
+        echo "OUT_DIR not specified"
+        exit 1
+    fi
+    BUILD_DIR=$OUT_DIR/witnesscalc
 fi
-BUILD_DIR=$OUT_DIR/witnesscalc
+WITNESSCALC_REPO=${WITNESSCALC_REPO:-https://github.com/zkmopro/witnesscalc.git}
 BINARY_PATH=$BUILD_DIR/build/witnesscalc/package/bin
 
 # If binary exists, exit
@@ -17,6 +20,6 @@ if [ -e $BINARY_PATH ]; then
 fi
 
 rm -rf $BUILD_DIR
-git clone https://github.com/zkmopro/witnesscalc.git $BUILD_DIR
+git clone $WITNESSCALC_REPO $BUILD_DIR
 cd $BUILD_DIR
 git submodule update --init --recursive
\ No newline at end of file
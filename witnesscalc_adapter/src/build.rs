@@ -0,0 +1,2199 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    env, fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+    sync::OnceLock,
+    time::{Duration, Instant},
+};
+
+use serde_json::json;
+
+use crate::circuit::{self, CircuitMetadata};
+
+mod cache;
+mod cc_backend;
+mod cross;
+mod error;
+mod gmp;
+mod prebuilt;
+mod preflight;
+mod runtime;
+mod source;
+mod symbols;
+mod target;
+
+use cache::CacheEntry;
+pub use error::BuildError;
+use gmp::ExternalGmp;
+use prebuilt::{Manifest, ManifestCircuit};
+use source::WitnesscalcSource;
+
+/// How the circuits and the witnesscalc runtime are compiled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Backend {
+    /// The witnesscalc CMake build through `make`, by default with GMP built by
+    /// `build_gmp.sh`. Requires cmake, m4 and nasm.
+    #[default]
+    Make,
+    /// Compiles the sources directly with the `cc` crate, respecting `CXX` and `CXXFLAGS`,
+    /// and by default links the system GMP. Only needs a C++ compiler and the GMP
+    /// development files, but only supports building for the host.
+    Cc,
+}
+
+/// Where the GMP library the circuits are linked against comes from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gmp {
+    /// Built from source in the witnesscalc tree by `build_gmp.sh` and linked statically.
+    Bundled,
+    /// The GMP installed on the system, found with pkg-config.
+    System,
+    /// The GMP built by the `gmp-mpfr-sys` crate, which also links it. The crate calling the
+    /// build must depend on `gmp-mpfr-sys` directly, for cargo to pass its build to the
+    /// build script, and reference it (e.g. `use gmp_mpfr_sys as _;`) so it is linked.
+    GmpMpfrSys,
+}
+
+/// Configuration for compiling circuit witness generators from `build.rs`.
+///
+/// ```no_run
+/// witnesscalc_adapter::Build::new()
+///     .circuits_dir("./testdata")
+///     .circuit("sha256")
+///     .jobs(8)
+///     .cxx_flag("-O3")
+///     .compile()
+///     .expect("Failed to build circuits");
+/// ```
+#[derive(Clone, Debug)]
+pub struct Build {
+    circuits_dir: Option<PathBuf>,
+    circuits: Vec<String>,
+    circuit_versions: HashMap<String, String>,
+    witnesscalc_repo: Option<String>,
+    witnesscalc_src: Option<PathBuf>,
+    witnesscalc_version_srcs: HashMap<String, PathBuf>,
+    witnesscalc_revisions: HashMap<String, String>,
+    gmp_archive: Option<PathBuf>,
+    cache: bool,
+    cache_dir: Option<PathBuf>,
+    backend: Option<Backend>,
+    gmp: Option<Gmp>,
+    portable: Option<bool>,
+    cpu_dispatch: Option<bool>,
+    jobs: Option<usize>,
+    cxx_flags: Vec<String>,
+    target: Option<String>,
+    gmp_build_target: Option<String>,
+    make_target: Option<String>,
+    prebuilt_dir: Option<PathBuf>,
+    export_bundle: Option<PathBuf>,
+    skip_build: Option<bool>,
+    cargo_metadata: bool,
+}
+
+impl Default for Build {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Build {
+    pub fn new() -> Self {
+        Self {
+            circuits_dir: None,
+            circuits: Vec::new(),
+            circuit_versions: HashMap::new(),
+            witnesscalc_repo: None,
+            witnesscalc_src: None,
+            witnesscalc_version_srcs: HashMap::new(),
+            witnesscalc_revisions: HashMap::new(),
+            gmp_archive: None,
+            cache: true,
+            cache_dir: None,
+            backend: None,
+            gmp: None,
+            portable: None,
+            cpu_dispatch: None,
+            jobs: None,
+            cxx_flags: Vec::new(),
+            target: None,
+            gmp_build_target: None,
+            make_target: None,
+            prebuilt_dir: None,
+            export_bundle: None,
+            skip_build: None,
+            cargo_metadata: true,
+        }
+    }
+
+    /// Directory containing the `<circuit name>.cpp` and `<circuit name>.dat` files.
+    pub fn circuits_dir(&mut self, circuits_dir: impl AsRef<Path>) -> &mut Self {
+        self.circuits_dir = Some(circuits_dir.as_ref().to_path_buf());
+        self
+    }
+
+    /// Only builds the given circuit. Can be called several times; by default every
+    /// circuit in the circuits directory is built.
+    pub fn circuit(&mut self, name: impl Into<String>) -> &mut Self {
+        self.circuits.push(name.into());
+        self
+    }
+
+    pub fn circuits<I, S>(&mut self, names: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.circuits.extend(names.into_iter().map(Into::into));
+        self
+    }
+
+    /// Builds a circuit against the given witnesscalc runtime version (e.g. `"v2.2.0"`)
+    /// instead of the one detected from its generated `.cpp` file.
+    pub fn circuit_version(
+        &mut self,
+        name: impl Into<String>,
+        version: impl Into<String>,
+    ) -> &mut Self {
+        self.circuit_versions.insert(name.into(), version.into());
+        self
+    }
+
+    /// Git URL of the witnesscalc fork to clone, defaults to <https://github.com/zkmopro/witnesscalc.git>.
+    pub fn witnesscalc_repo(&mut self, url: impl Into<String>) -> &mut Self {
+        self.witnesscalc_repo = Some(url.into());
+        self
+    }
+
+    /// Local witnesscalc checkout or archive (`.tar`, `.tar.gz`, `.tar.xz`) to build from
+    /// instead of cloning, for builds without network access.
+    ///
+    /// Defaults to the `WITNESSCALC_SRC` environment variable. The v2.2.0 runtime is checked
+    /// out from the local git history; for sources without one, see
+    /// [`Build::witnesscalc_version_src`].
+    pub fn witnesscalc_src(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.witnesscalc_src = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Local source tree or archive for a witnesscalc runtime version (e.g. `"v2.2.0"`).
+    ///
+    /// Defaults to the `WITNESSCALC_SRC_<VERSION>` environment variable, e.g. `WITNESSCALC_SRC_V2_2_0`.
+    pub fn witnesscalc_version_src(
+        &mut self,
+        version: impl Into<String>,
+        path: impl AsRef<Path>,
+    ) -> &mut Self {
+        self.witnesscalc_version_srcs
+            .insert(version.into(), path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Commit a witnesscalc runtime version (e.g. `"v2.2.0"`) is pinned to, overriding the
+    /// commit pinned in this crate.
+    ///
+    /// Defaults to the `WITNESSCALC_REV_<VERSION>` environment variable, e.g. `WITNESSCALC_REV_V2_2_0`.
+    pub fn witnesscalc_revision(
+        &mut self,
+        version: impl Into<String>,
+        commit: impl Into<String>,
+    ) -> &mut Self {
+        self.witnesscalc_revisions
+            .insert(version.into(), commit.into());
+        self
+    }
+
+    /// Local `gmp-6.2.1.tar.xz` used instead of downloading GMP. Its checksum is verified
+    /// like a downloaded archive.
+    ///
+    /// Defaults to the `WITNESSCALC_GMP_ARCHIVE` environment variable.
+    pub fn gmp_archive(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.gmp_archive = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Whether to share the fetched witnesscalc sources and the GMP build with other crates
+    /// and builds through the build cache, enabled by default.
+    pub fn cache(&mut self, cache: bool) -> &mut Self {
+        self.cache = cache;
+        self
+    }
+
+    /// Directory of the shared build cache.
+    ///
+    /// Defaults to the `WITNESSCALC_CACHE_DIR` environment variable, then to a
+    /// `witnesscalc-adapter` directory in the user's cache directory.
+    pub fn cache_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.cache_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// How the circuits are compiled.
+    ///
+    /// Defaults to the `WITNESSCALC_BACKEND` environment variable (`make` or `cc`), then to
+    /// [`Backend::Make`].
+    pub fn backend(&mut self, backend: Backend) -> &mut Self {
+        self.backend = Some(backend);
+        self
+    }
+
+    /// Where the GMP library comes from.
+    ///
+    /// Defaults to the `WITNESSCALC_GMP` environment variable (`bundled`, `system` or
+    /// `gmp-mpfr-sys`), then to [`Gmp::Bundled`] for the make backend and [`Gmp::System`]
+    /// for the cc backend.
+    pub fn gmp(&mut self, gmp: Gmp) -> &mut Self {
+        self.gmp = Some(gmp);
+        self
+    }
+
+    /// Whether to build the field arithmetic with circom's generic C++ implementation instead
+    /// of the x86_64 or arm64 assembly, which needs nasm on x86_64.
+    ///
+    /// Defaults to the `WITNESSCALC_PORTABLE` environment variable (`1` or `0`), then to
+    /// portable builds for architectures without assembly, e.g. riscv64. The cc backend
+    /// always builds the portable implementation.
+    pub fn portable(&mut self, portable: bool) -> &mut Self {
+        self.portable = Some(portable);
+        self
+    }
+
+    /// Whether to also build every circuit with the portable field arithmetic on x86_64, and
+    /// pick the variant at runtime from the CPU features, see [`crate::FieldArithmetic`].
+    /// Doubles the build time, but the binary also runs on CPUs without ADX and BMI2. The
+    /// bundled GMP is then built fat, with the code of every x86_64 CPU.
+    ///
+    /// Defaults to the `WITNESSCALC_CPU_DISPATCH` environment variable (`1` or `0`), then to
+    /// disabled. Only applies to non-portable builds with the make backend.
+    pub fn cpu_dispatch(&mut self, cpu_dispatch: bool) -> &mut Self {
+        self.cpu_dispatch = Some(cpu_dispatch);
+        self
+    }
+
+    /// Number of parallel jobs passed to `make`.
+    ///
+    /// By default `make` takes its job slots from cargo's jobserver, sharing `NUM_JOBS` with
+    /// the rest of the build.
+    pub fn jobs(&mut self, jobs: usize) -> &mut Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Adds a flag to the `CXXFLAGS` used to compile the circuits.
+    pub fn cxx_flag(&mut self, flag: impl Into<String>) -> &mut Self {
+        self.cxx_flags.push(flag.into());
+        self
+    }
+
+    pub fn cxx_flags<I, S>(&mut self, flags: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.cxx_flags.extend(flags.into_iter().map(Into::into));
+        self
+    }
+
+    /// Target triple to build for, defaults to the `TARGET` set by cargo.
+    pub fn target(&mut self, target: impl Into<String>) -> &mut Self {
+        self.target = Some(target.into());
+        self
+    }
+
+    /// Platform argument of witnesscalc's `build_gmp.sh` (e.g. `"android"`), overriding the
+    /// one the target triple maps to.
+    ///
+    /// Defaults to the `WITNESSCALC_GMP_TARGET` environment variable.
+    pub fn gmp_build_target(&mut self, platform: impl Into<String>) -> &mut Self {
+        self.gmp_build_target = Some(platform.into());
+        self
+    }
+
+    /// Target of the witnesscalc `Makefile` (e.g. `"arm64_host"`), overriding the one the
+    /// target triple maps to.
+    ///
+    /// Defaults to the `WITNESSCALC_MAKE_TARGET` environment variable. Cross-compiling to a
+    /// target this crate does not know requires both this and [`Build::gmp_build_target`].
+    pub fn make_target(&mut self, make_target: impl Into<String>) -> &mut Self {
+        self.make_target = Some(make_target.into());
+        self
+    }
+
+    /// Directory of prebuilt libraries to link instead of building anything: a
+    /// `libwitnesscalc_<circuit name>.a` per circuit, `libfr.a`, `libgmp.a` unless another
+    /// [`Gmp`] is used, and a `witnesscalc-manifest.json` naming the target, the witnesscalc
+    /// revisions and the hashes of the circuit sources they were built from. The manifest must
+    /// match the circuits in the circuits directory, so stale libraries are never linked.
+    ///
+    /// Defaults to the `WITNESSCALC_PREBUILT_DIR` environment variable.
+    pub fn prebuilt_dir(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.prebuilt_dir = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Directory or `.tar`, `.tar.gz` or `.tgz` archive to export the built circuits to, for
+    /// other builds and build systems to link: the static libraries, the
+    /// `include/witnesscalc_<circuit name>.h` headers, the `.dat` files and the manifest read
+    /// by [`Build::prebuilt_dir`].
+    ///
+    /// Defaults to the `WITNESSCALC_EXPORT_BUNDLE` environment variable.
+    pub fn export_bundle(&mut self, path: impl AsRef<Path>) -> &mut Self {
+        self.export_bundle = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    /// Whether to skip fetching and compiling anything, for builds without the C++ toolchain
+    /// that only document or type-check the crate. Nothing is linked, and the functions
+    /// generated by [`crate::witness`] fail with [`crate::NotBuilt`].
+    ///
+    /// Defaults to the `WITNESSCALC_SKIP_BUILD` environment variable (`1` or `0`), then to
+    /// skipping on docs.rs, which sets `DOCS_RS`, and with the `mock` feature.
+    pub fn skip_build(&mut self, skip_build: bool) -> &mut Self {
+        self.skip_build = Some(skip_build);
+        self
+    }
+
+    /// Whether to print `cargo:` link and rerun directives, enabled by default.
+    pub fn cargo_metadata(&mut self, cargo_metadata: bool) -> &mut Self {
+        self.cargo_metadata = cargo_metadata;
+        self
+    }
+
+    fn cargo(&self, directive: &str) {
+        if self.cargo_metadata {
+            println!("cargo:{}", directive);
+        }
+    }
+
+    /// Reads a path option, falling back to an environment variable.
+    fn path_option(&self, option: Option<&PathBuf>, env_var: &str) -> Option<PathBuf> {
+        self.cargo(&format!("rerun-if-env-changed={}", env_var));
+        option
+            .cloned()
+            .or_else(|| env::var_os(env_var).map(PathBuf::from))
+    }
+
+    fn witnesscalc_source(&self) -> WitnesscalcSource {
+        match self.path_option(self.witnesscalc_src.as_ref(), "WITNESSCALC_SRC") {
+            Some(path) => WitnesscalcSource::Local(path),
+            None => WitnesscalcSource::Git(self.witnesscalc_repo.clone()),
+        }
+    }
+
+    fn version_source(&self, version: &str) -> Option<PathBuf> {
+        let env_var = format!("WITNESSCALC_SRC_{}", version_env_suffix(version));
+        self.path_option(self.witnesscalc_version_srcs.get(version), &env_var)
+    }
+
+    fn revision(&self, version: &str, warnings: &mut Warnings) -> Option<String> {
+        let revision = self.pinned_revision(version);
+        // Also warns for the default fork, until every runtime has a commit in
+        // `source::PINNED_REVISIONS`
+        if revision.is_none() {
+            warnings.warn(format!(
+                "witnesscalc {} is not pinned to a commit, set WITNESSCALC_REV_{} to pin it",
+                version,
+                version_env_suffix(version)
+            ));
+        }
+        revision
+    }
+
+    fn pinned_revision(&self, version: &str) -> Option<String> {
+        let env_var = format!("WITNESSCALC_REV_{}", version_env_suffix(version));
+        self.cargo(&format!("rerun-if-env-changed={}", env_var));
+        self.witnesscalc_revisions
+            .get(version)
+            .cloned()
+            .or_else(|| env::var(&env_var).ok())
+            .or_else(|| source::pinned_revision(version).map(String::from))
+    }
+
+    fn cache_entry(&self, target: &str, with_gmp: bool, fat_gmp: bool) -> Option<CacheEntry> {
+        if !self.cache {
+            return None;
+        }
+        let root = self
+            .path_option(self.cache_dir.as_ref(), "WITNESSCALC_CACHE_DIR")
+            .or_else(cache::default_dir)?;
+        // Each runtime is identified by its pinned revision, or where it comes from
+        let source = runtime::RUNTIMES
+            .iter()
+            .map(|runtime| {
+                let id = self.pinned_revision(runtime.version).unwrap_or_else(|| {
+                    match self.version_source(runtime.version) {
+                        Some(path) => WitnesscalcSource::Local(path).id(),
+                        None => self.witnesscalc_source().id(),
+                    }
+                });
+                format!("{} {}", runtime.version, id)
+            })
+            .collect::<Vec<_>>()
+            .join("\n");
+        Some(CacheEntry::new(&root, target, &source, with_gmp, fat_gmp))
+    }
+
+    /// Identifies the sources of every witnesscalc tree the build fetches.
+    fn source_id(&self) -> String {
+        let mut id = self.witnesscalc_source().id();
+        for runtime in runtime::RUNTIMES
+            .iter()
+            .filter(|runtime| !runtime.is_base())
+        {
+            if let Some(path) = self.version_source(runtime.version) {
+                let source = WitnesscalcSource::Local(path);
+                id.push_str(&format!("\n{} {}", runtime.version, source.id()));
+            }
+        }
+        id
+    }
+
+    fn resolve_backend(&self, target: &str) -> Result<Backend, BuildError> {
+        self.cargo("rerun-if-env-changed=WITNESSCALC_BACKEND");
+        let backend = match self.backend {
+            Some(backend) => backend,
+            None => match env::var("WITNESSCALC_BACKEND").as_deref() {
+                Err(_) | Ok("") | Ok("make") => Backend::Make,
+                Ok("cc") => Backend::Cc,
+                Ok(other) => {
+                    return Err(BuildError::Config(format!(
+                        "Unknown WITNESSCALC_BACKEND {}, expected make or cc",
+                        other
+                    )))
+                }
+            },
+        };
+        if backend == Backend::Cc && env::var("HOST").is_ok_and(|host| host != target) {
+            return Err(BuildError::Config(format!(
+                "The cc backend only builds for the host, use the make backend for {}",
+                target
+            )));
+        }
+        Ok(backend)
+    }
+
+    fn target_config(&self, target: &str) -> Result<target::TargetConfig, BuildError> {
+        let string_option = |option: &Option<String>, env_var: &str| {
+            self.cargo(&format!("rerun-if-env-changed={}", env_var));
+            option
+                .clone()
+                .or_else(|| env::var(env_var).ok().filter(|value| !value.is_empty()))
+        };
+        target::resolve(
+            target,
+            &env::var("HOST").unwrap_or_else(|_| target.to_string()),
+            string_option(&self.gmp_build_target, "WITNESSCALC_GMP_TARGET"),
+            string_option(&self.make_target, "WITNESSCALC_MAKE_TARGET"),
+        )
+    }
+
+    fn resolve_skip_build(&self) -> Result<bool, BuildError> {
+        self.cargo("rerun-if-env-changed=WITNESSCALC_SKIP_BUILD");
+        self.cargo("rerun-if-env-changed=DOCS_RS");
+        if let Some(skip_build) = self.skip_build {
+            return Ok(skip_build);
+        }
+        match env::var("WITNESSCALC_SKIP_BUILD").as_deref() {
+            Err(_) | Ok("") => Ok(cfg!(feature = "mock") || env::var_os("DOCS_RS").is_some()),
+            Ok("1") | Ok("true") => Ok(true),
+            Ok("0") | Ok("false") => Ok(false),
+            Ok(other) => Err(BuildError::Config(format!(
+                "Unknown WITNESSCALC_SKIP_BUILD {}, expected 1 or 0",
+                other
+            ))),
+        }
+    }
+
+    fn resolve_gmp(&self, backend: Backend) -> Result<Gmp, BuildError> {
+        self.cargo("rerun-if-env-changed=WITNESSCALC_GMP");
+        if let Some(gmp) = self.gmp {
+            return Ok(gmp);
+        }
+        match env::var("WITNESSCALC_GMP").as_deref() {
+            Err(_) | Ok("") => Ok(match backend {
+                Backend::Make => Gmp::Bundled,
+                Backend::Cc => Gmp::System,
+            }),
+            Ok("bundled") => Ok(Gmp::Bundled),
+            Ok("system") => Ok(Gmp::System),
+            Ok("gmp-mpfr-sys") => Ok(Gmp::GmpMpfrSys),
+            Ok(other) => Err(BuildError::Config(format!(
+                "Unknown WITNESSCALC_GMP {}, expected bundled, system or gmp-mpfr-sys",
+                other
+            ))),
+        }
+    }
+
+    fn resolve_portable(&self, target: &str, backend: Backend) -> Result<bool, BuildError> {
+        self.cargo("rerun-if-env-changed=WITNESSCALC_PORTABLE");
+        if backend == Backend::Cc {
+            return Ok(true);
+        }
+        if let Some(portable) = self.portable {
+            return Ok(portable);
+        }
+        match env::var("WITNESSCALC_PORTABLE").as_deref() {
+            Err(_) | Ok("") => Ok(!target::has_asm(target)),
+            Ok("1") | Ok("true") => Ok(true),
+            Ok("0") | Ok("false") => Ok(false),
+            Ok(other) => Err(BuildError::Config(format!(
+                "Unknown WITNESSCALC_PORTABLE {}, expected 1 or 0",
+                other
+            ))),
+        }
+    }
+
+    fn resolve_cpu_dispatch(
+        &self,
+        target: &str,
+        backend: Backend,
+        portable: bool,
+        warnings: &mut Warnings,
+    ) -> Result<bool, BuildError> {
+        self.cargo("rerun-if-env-changed=WITNESSCALC_CPU_DISPATCH");
+        let cpu_dispatch = match self.cpu_dispatch {
+            Some(cpu_dispatch) => cpu_dispatch,
+            None => match env::var("WITNESSCALC_CPU_DISPATCH").as_deref() {
+                Err(_) | Ok("") | Ok("0") | Ok("false") => false,
+                Ok("1") | Ok("true") => true,
+                Ok(other) => {
+                    return Err(BuildError::Config(format!(
+                        "Unknown WITNESSCALC_CPU_DISPATCH {}, expected 1 or 0",
+                        other
+                    )))
+                }
+            },
+        };
+        // Other builds only have one field arithmetic to pick
+        if cpu_dispatch && (backend != Backend::Make || portable || !target.starts_with("x86_64-"))
+        {
+            warnings.warn(format!(
+                "CPU dispatch only applies to non-portable make builds for x86_64, building {} \
+                 with a single field arithmetic",
+                target
+            ));
+            return Ok(false);
+        }
+        Ok(cpu_dispatch)
+    }
+
+    /// `CXXFLAGS` with the configured flags appended, if there are any.
+    fn cxx_flags_env(&self) -> Option<String> {
+        if self.cxx_flags.is_empty() {
+            return None;
+        }
+        let mut cxx_flags = env::var("CXXFLAGS").unwrap_or_default();
+        for flag in &self.cxx_flags {
+            if !cxx_flags.is_empty() {
+                cxx_flags.push(' ');
+            }
+            cxx_flags.push_str(flag);
+        }
+        Some(cxx_flags)
+    }
+
+    /// Everything besides its sources a circuit library depends on, including whether its
+    /// symbols are renamed as it is not built against the `primary` runtime.
+    #[allow(clippy::too_many_arguments)]
+    fn build_options(
+        &self,
+        runtime: &runtime::Runtime,
+        primary: &runtime::Runtime,
+        backend: Backend,
+        gmp: Gmp,
+        portable: bool,
+        cpu_dispatch: bool,
+        witnesscalc_build_target: &str,
+    ) -> String {
+        format!(
+            "{} {:?} {:?} portable={} cpu_dispatch={} {} private_symbols={} {} {:?} {:?}",
+            env!("CARGO_PKG_VERSION"),
+            backend,
+            gmp,
+            portable,
+            cpu_dispatch,
+            runtime.version,
+            !std::ptr::eq(runtime, primary),
+            witnesscalc_build_target,
+            self.pinned_revision(runtime.version),
+            self.cxx_flags_env().or_else(|| env::var("CXXFLAGS").ok()),
+        )
+    }
+
+    /// The tools fetching the sources and building for `target` runs, `symbol_tools` when
+    /// libraries are given private symbol names.
+    fn required_tools(
+        &self,
+        target: &str,
+        backend: Backend,
+        gmp: Gmp,
+        portable: bool,
+        cmake_settings: bool,
+        symbol_tools: Option<&symbols::SymbolTools>,
+    ) -> Vec<preflight::Tool> {
+        let host = env::var("HOST").unwrap_or_else(|_| target.to_string());
+        let mut tools = vec![preflight::Tool::compiler(
+            true,
+            target,
+            &host,
+            "the circuits and the witnesscalc runtime",
+        )];
+        if let WitnesscalcSource::Git(_) = self.witnesscalc_source() {
+            tools.push(preflight::Tool::new("git", "cloning witnesscalc"));
+        }
+        if backend == Backend::Make {
+            tools.push(preflight::Tool::new("make", "the witnesscalc build"));
+            let cmake = preflight::Tool::new("cmake", "the witnesscalc build");
+            // Portable and cross builds pass their settings to CMake as a toolchain file
+            tools.push(if cmake_settings {
+                cmake.min_version(3, 21)
+            } else {
+                cmake
+            });
+            if !portable && target.starts_with("x86_64") {
+                tools.push(preflight::Tool::new(
+                    "nasm",
+                    "the x86_64 field arithmetic assembly",
+                ));
+            }
+        }
+        if gmp == Gmp::Bundled {
+            tools.push(preflight::Tool::compiler(false, target, &host, "GMP"));
+            tools.push(preflight::Tool::new("m4", "GMP"));
+            if backend != Backend::Make {
+                tools.push(preflight::Tool::new("make", "GMP"));
+            }
+            if self
+                .path_option(self.gmp_archive.as_ref(), "WITNESSCALC_GMP_ARCHIVE")
+                .is_none()
+            {
+                tools.push(preflight::Tool::new("curl", "downloading GMP"));
+            }
+        }
+        if let Some(symbol_tools) = symbol_tools {
+            for program in [&symbol_tools.nm, &symbol_tools.objcopy] {
+                tools.push(preflight::Tool::symbol_tool(
+                    program,
+                    target,
+                    &host,
+                    "linking several witnesscalc runtimes or field arithmetics",
+                ));
+            }
+        }
+        let mut local_sources = runtime::RUNTIMES
+            .iter()
+            .filter_map(|runtime| self.version_source(runtime.version))
+            .collect::<Vec<_>>();
+        if let WitnesscalcSource::Local(path) = self.witnesscalc_source() {
+            local_sources.push(path);
+        }
+        let tar_purpose = if gmp == Gmp::Bundled {
+            Some("extracting GMP")
+        } else if local_sources.iter().any(|path| !path.is_dir()) {
+            Some("extracting the witnesscalc sources")
+        } else if self
+            .path_option(self.export_bundle.as_ref(), "WITNESSCALC_EXPORT_BUNDLE")
+            .is_some_and(|path| prebuilt::tar_flags(&path).is_some())
+        {
+            Some("archiving the exported bundle")
+        } else {
+            None
+        };
+        if let Some(purpose) = tar_purpose {
+            tools.push(preflight::Tool::new("tar", purpose));
+        }
+        tools
+    }
+
+    /// Whether libraries are given private symbol names: those of the circuits built against
+    /// another runtime than the primary one of `circuit_runtimes`, and all for CPU dispatch.
+    fn renames_symbols(circuit_runtimes: &[&runtime::Runtime], cpu_dispatch: bool) -> bool {
+        let primary = runtime::primary(circuit_runtimes);
+        cpu_dispatch
+            || circuit_runtimes
+                .iter()
+                .any(|runtime| !std::ptr::eq(*runtime, primary))
+    }
+
+    /// Builds GMP in `witnesscalc_path` unless it already is, `fat` for CPU dispatch.
+    fn build_gmp(
+        &self,
+        witnesscalc_path: &Path,
+        target_config: &target::TargetConfig,
+        toolchain: Option<&cross::Toolchain>,
+        fat: bool,
+    ) -> Result<(), BuildError> {
+        let target_dir = witnesscalc_path
+            .join("depends")
+            .join("gmp")
+            .join(&target_config.gmp_lib_folder);
+        if target_dir.exists() {
+            return Ok(());
+        }
+        let gmp_archive = self.path_option(self.gmp_archive.as_ref(), "WITNESSCALC_GMP_ARCHIVE");
+        source::install_gmp_archive(gmp_archive.as_deref(), witnesscalc_path)?;
+        let cross = target_config.cross_host.as_deref().zip(toolchain);
+        if cross.is_some() || fat {
+            return gmp::build(
+                witnesscalc_path,
+                &target_config.gmp_lib_folder,
+                cross,
+                fat,
+                |make| self.configure_jobs(make),
+            );
+        }
+        error::run(
+            Command::new("bash")
+                .current_dir(witnesscalc_path)
+                .arg("./build_gmp.sh")
+                .arg(&target_config.gmp_build_target),
+        )?;
+        Ok(())
+    }
+
+    /// Runs `make` with the configured number of jobs, or within cargo's job slots.
+    fn configure_jobs(&self, make: &mut Command) {
+        match self.jobs {
+            Some(jobs) => {
+                make.arg(format!("-j{}", jobs))
+                    .env("CMAKE_BUILD_PARALLEL_LEVEL", jobs.to_string());
+            }
+            None => match jobserver() {
+                // Build in parallel with cargo's job slots, rather than starting as many
+                // jobs again next to the rest of the build. CMAKE_BUILD_PARALLEL_LEVEL is
+                // left unset, as it would pass its own -j to the make CMake runs
+                Some(client) => client.configure_make(make),
+                None => {
+                    if let Ok(num_jobs) = env::var("NUM_JOBS") {
+                        make.arg(format!("-j{}", num_jobs))
+                            .env("CMAKE_BUILD_PARALLEL_LEVEL", num_jobs);
+                    }
+                }
+            },
+        }
+    }
+
+    /// Finds the `.cpp` files of the circuits to build in `circuits_dir`.
+    fn circuit_files(&self, circuits_dir: &Path) -> Result<Vec<PathBuf>, BuildError> {
+        let circuit_files = fs::read_dir(circuits_dir)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| entry.map(|entry| entry.path()))
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .map_err(BuildError::io("Failed to read circuits directory"))?
+            .into_iter()
+            .filter(|path| path.extension().is_some() && path.extension().unwrap() == "cpp")
+            .filter(|path| {
+                self.circuits.is_empty()
+                    || self
+                        .circuits
+                        .iter()
+                        .any(|name| path.file_stem().unwrap() == name.as_str())
+            })
+            .collect::<Vec<_>>();
+        for name in &self.circuits {
+            if !circuit_files
+                .iter()
+                .any(|path| path.file_stem().unwrap() == name.as_str())
+            {
+                return Err(BuildError::Config(format!(
+                    "Circuit {} not found in {}",
+                    name,
+                    circuits_dir.display()
+                )));
+            }
+        }
+        Ok(circuit_files)
+    }
+
+    /// Compiles the circuits and links them to the crate being built.
+    ///
+    /// The returned [`BuildOutput`] is also written as JSON to `witnesscalc-build.json` in
+    /// `OUT_DIR`, for tools inspecting the build.
+    ///
+    /// # Errors
+    ///
+    /// Fails with a [`BuildError`] rather than panicking: [`BuildError::Config`] for an
+    /// invalid configuration or missing build dependencies, [`BuildError::Io`] and
+    /// [`BuildError::Command`] when a file operation or an external command fails,
+    /// [`BuildError::Integrity`] when the sources do not match their pinned revision or
+    /// checksum, and [`BuildError::Compile`] when the `cc` backend fails to compile.
+    /// Problems the build recovers from, e.g. `make` failing after building every library,
+    /// are returned in [`BuildOutput::warnings`] instead.
+    pub fn compile(&self) -> Result<BuildOutput, BuildError> {
+        let output = self.compile_circuits()?;
+        if let Ok(out_dir) = env::var("OUT_DIR") {
+            let path = Path::new(&out_dir).join(OUTPUT_NAME);
+            fs::write(&path, output.to_json()).map_err(BuildError::io(format!(
+                "Failed to write the build output {}",
+                path.display()
+            )))?;
+        }
+        Ok(output)
+    }
+
+    fn compile_circuits(&self) -> Result<BuildOutput, BuildError> {
+        let mut warnings = Warnings {
+            cargo_metadata: self.cargo_metadata,
+            messages: Vec::new(),
+        };
+        // Tell `witness!` whether the baseline variants of the circuits are linked, and
+        // whether any circuit is
+        self.cargo("rustc-check-cfg=cfg(witnesscalc_cpu_dispatch)");
+        self.cargo("rustc-check-cfg=cfg(witnesscalc_stub)");
+        if self.resolve_skip_build()? {
+            self.cargo("rustc-cfg=witnesscalc_stub");
+            return Ok(BuildOutput {
+                circuits: Vec::new(),
+                lib_dir: PathBuf::new(),
+                compile_times: BTreeMap::new(),
+                warnings: Vec::new(),
+            });
+        }
+        let target = match &self.target {
+            Some(target) => target.clone(),
+            None => env::var("TARGET").map_err(|_| {
+                BuildError::Config(
+                    "Cargo did not provide the TARGET environment variable".to_string(),
+                )
+            })?,
+        };
+        let circuits_dir = self
+            .circuits_dir
+            .as_deref()
+            .ok_or_else(|| BuildError::Config("circuits_dir must be set".to_string()))?;
+
+        let out_dir =
+            env::var("OUT_DIR").map_err(|_| BuildError::Config("OUT_DIR not set".to_string()))?;
+
+        if !Path::is_dir(circuits_dir) {
+            return Err(BuildError::Config(format!(
+                "circuits_dir must be a directory: {}",
+                circuits_dir.display()
+            )));
+        }
+        if self.circuits.is_empty() {
+            // Picks up circuits added to the directory
+            self.cargo(&format!("rerun-if-changed={}", circuits_dir.display()));
+        }
+        self.cargo("rerun-if-env-changed=CXXFLAGS");
+        let circuit_files = self.circuit_files(circuits_dir)?;
+
+        let witnesscalc_path = Path::new(&out_dir).join(Path::new("witnesscalc"));
+        if let Some(prebuilt_dir) =
+            self.path_option(self.prebuilt_dir.as_ref(), "WITNESSCALC_PREBUILT_DIR")
+        {
+            return self.link_prebuilt(&prebuilt_dir, &target, &circuit_files, &witnesscalc_path);
+        }
+        let circuit_sources = self.read_circuits(&circuit_files)?;
+        let circuit_runtimes = circuit_sources
+            .iter()
+            .map(|(_, _, _, runtime)| *runtime)
+            .collect::<Vec<_>>();
+        let build = self.resolve_source_build(
+            target,
+            Path::new(&out_dir),
+            &circuit_runtimes,
+            &mut warnings,
+        )?;
+
+        println!("Detected target: {}", build.target);
+        preflight::check(
+            &self.required_tools(
+                &build.target,
+                build.backend,
+                build.gmp,
+                build.portable,
+                build.portable || build.cpu_dispatch || build.target_config.cross_host.is_some(),
+                Self::renames_symbols(&circuit_runtimes, build.cpu_dispatch)
+                    .then_some(&build.symbol_tools),
+            ),
+        )?;
+        self.prepare_sources(&build)?;
+
+        let mut output = SourceBuildOutput::default();
+        let runtime_circuits = self.plan_circuits(&build, circuit_sources, &mut output)?;
+        for (runtime, circuits) in &runtime_circuits {
+            // The primary runtime is always built as it also provides the shared libraries
+            if !circuits.is_empty() || std::ptr::eq(*runtime, build.primary) {
+                self.build_runtime(&build, runtime, circuits, &mut output, &mut warnings)?;
+            }
+        }
+        self.link_source_build(&build, &output.runtime_lib_dirs);
+
+        if let Some(export_bundle) =
+            self.path_option(self.export_bundle.as_ref(), "WITNESSCALC_EXPORT_BUNDLE")
+        {
+            self.export_source_build(
+                &build,
+                &export_bundle,
+                &runtime_circuits,
+                output.bundle_files,
+                output.manifest_circuits,
+            )?;
+        }
+
+        Ok(BuildOutput {
+            circuits: output.built_circuits,
+            lib_dir: build.lib_dir(),
+            compile_times: output.compile_times,
+            warnings: warnings.messages,
+        })
+    }
+
+    /// Reads the `.cpp` and `.dat` files of the circuits, and the runtime each is built
+    /// against.
+    fn read_circuits(&self, circuit_files: &[PathBuf]) -> Result<Vec<CircuitSource>, BuildError> {
+        let mut circuit_sources = Vec::new();
+        for path in circuit_files {
+            let circuit_name = path.file_stem().unwrap().to_str().unwrap();
+            let circuit_dat = path.with_extension("dat");
+            self.cargo(&format!("rerun-if-changed={}", path.display()));
+            self.cargo(&format!("rerun-if-changed={}", circuit_dat.display()));
+            let circuit_cpp = fs::read_to_string(path).map_err(BuildError::io(format!(
+                "Failed to read circuit .cpp file {}",
+                path.display()
+            )))?;
+            let circuit_dat_bytes = fs::read(&circuit_dat).map_err(BuildError::io(format!(
+                "Failed to read circuit .dat file {}",
+                circuit_dat.display()
+            )))?;
+            let circuit_runtime = match self.circuit_versions.get(circuit_name) {
+                Some(version) => runtime::find(version)?,
+                None => runtime::detect(circuit_name, &circuit_cpp)?,
+            };
+            circuit_sources.push((
+                path.clone(),
+                circuit_cpp,
+                circuit_dat_bytes,
+                circuit_runtime,
+            ));
+        }
+        Ok(circuit_sources)
+    }
+
+    /// Resolves the settings of a build from source for the runtimes of the circuits.
+    fn resolve_source_build(
+        &self,
+        target: String,
+        out_dir: &Path,
+        circuit_runtimes: &[&'static runtime::Runtime],
+        warnings: &mut Warnings,
+    ) -> Result<SourceBuild, BuildError> {
+        let host = env::var("HOST").unwrap_or_else(|_| target.clone());
+        let backend = self.resolve_backend(&target)?;
+        let gmp = self.resolve_gmp(backend)?;
+        let portable = self.resolve_portable(&target, backend)?;
+        let cpu_dispatch = self.resolve_cpu_dispatch(&target, backend, portable, warnings)?;
+        let external_gmp = match gmp {
+            Gmp::Bundled => None,
+            gmp => Some(ExternalGmp::probe(gmp, self.cargo_metadata)?),
+        };
+        let target_config = self.target_config(&target)?;
+        // Cross-compiled Linux targets are built with the toolchain cargo configures for them
+        let toolchain = match &target_config.cross_host {
+            Some(_) => Some(cross::Toolchain::new(&target, &host)?),
+            None => None,
+        };
+        let primary = runtime::primary(circuit_runtimes);
+        let build_options = runtime::RUNTIMES
+            .iter()
+            .map(|runtime| {
+                (
+                    runtime.version,
+                    self.build_options(
+                        runtime,
+                        primary,
+                        backend,
+                        gmp,
+                        portable,
+                        cpu_dispatch,
+                        &target_config.make_target,
+                    ),
+                )
+            })
+            .collect();
+        Ok(SourceBuild {
+            symbol_tools: symbols::SymbolTools::resolve(&target, &host),
+            witnesscalc_path: out_dir.join("witnesscalc"),
+            out_dir: out_dir.to_path_buf(),
+            target,
+            host,
+            backend,
+            gmp,
+            external_gmp,
+            portable,
+            cpu_dispatch,
+            // GMP tuned to the build machine would defeat the baseline variant
+            fat_gmp: cpu_dispatch && gmp == Gmp::Bundled,
+            target_config,
+            toolchain,
+            primary,
+            build_options,
+        })
+    }
+
+    /// Fetches the witnesscalc sources, or copies them from the build cache, and builds the
+    /// bundled GMP.
+    fn prepare_sources(&self, build: &SourceBuild) -> Result<(), BuildError> {
+        let witnesscalc_path = &build.witnesscalc_path;
+        // Drop the trees fetched from other sources or with another GMP build, or the circuit
+        // data written by a prebuilt build, so a changed WITNESSCALC_SRC is fetched again
+        let mut source_id = self.source_id();
+        if build.fat_gmp {
+            source_id.push_str("\nfat GMP");
+        }
+        if witnesscalc_path.exists()
+            && fs::read_to_string(witnesscalc_path.join(SOURCE_STAMP)).ok()
+                != Some(source_id.clone())
+        {
+            for runtime in runtime::RUNTIMES {
+                for tree in [
+                    runtime.tree(&build.out_dir),
+                    runtime.baseline_tree(&build.out_dir),
+                ] {
+                    if tree.exists() {
+                        fs::remove_dir_all(&tree).map_err(BuildError::io(format!(
+                            "Failed to remove the outdated witnesscalc tree {}",
+                            tree.display()
+                        )))?;
+                    }
+                }
+            }
+        }
+
+        // If the witnesscalc repo is not cloned, clone it, or copy it from the build cache
+        let bundled_gmp = build.gmp == Gmp::Bundled;
+        if !witnesscalc_path.exists() {
+            match self.cache_entry(&build.target, bundled_gmp, build.fat_gmp) {
+                Some(cache_entry) => cache_entry.install(witnesscalc_path, |tree| {
+                    self.witnesscalc_source().fetch(tree)?;
+                    if bundled_gmp {
+                        self.build_gmp(
+                            tree,
+                            &build.target_config,
+                            build.toolchain.as_ref(),
+                            build.fat_gmp,
+                        )?;
+                    }
+                    Ok(())
+                })?,
+                None => self.witnesscalc_source().fetch(witnesscalc_path)?,
+            }
+            fs::write(witnesscalc_path.join(SOURCE_STAMP), &source_id)
+                .map_err(BuildError::io("Failed to record the witnesscalc source"))?;
+        }
+        // If the witnesscalc library is not built, build it
+        if bundled_gmp {
+            self.build_gmp(
+                witnesscalc_path,
+                &build.target_config,
+                build.toolchain.as_ref(),
+                build.fat_gmp,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Groups the circuits by runtime, writing the data `witness!` embeds for those that are
+    /// not up to date.
+    fn plan_circuits(
+        &self,
+        build: &SourceBuild,
+        circuit_sources: Vec<CircuitSource>,
+        output: &mut SourceBuildOutput,
+    ) -> Result<Vec<(&'static runtime::Runtime, Vec<CircuitBuild>)>, BuildError> {
+        let mut runtime_circuits = runtime::RUNTIMES
+            .iter()
+            .map(|runtime| (runtime, Vec::new()))
+            .collect::<Vec<(_, Vec<CircuitBuild>)>>();
+        for (path, circuit_cpp, circuit_dat_bytes, circuit_runtime) in circuit_sources {
+            let circuit_name = path.file_stem().unwrap().to_str().unwrap();
+            let circuit_dat = path.with_extension("dat");
+
+            // Circuits whose inputs and build options are unchanged since the last build
+            // are neither rewritten nor recompiled
+            let cpp_sha256 = circuit::sha256_hex(circuit_cpp.as_bytes());
+            let dat_sha256 = circuit::sha256_hex(&circuit_dat_bytes);
+            let inputs_hash = circuit::sha256_hex(
+                [
+                    build.build_options[circuit_runtime.version].as_bytes(),
+                    cpp_sha256.as_bytes(),
+                    dat_sha256.as_bytes(),
+                ]
+                .concat()
+                .as_slice(),
+            );
+            output.manifest_circuits.insert(
+                circuit_name.to_string(),
+                ManifestCircuit {
+                    runtime: circuit_runtime,
+                    cpp_sha256,
+                    dat_sha256: dat_sha256.clone(),
+                },
+            );
+            let stamp = circuit_stamp(&circuit_runtime.tree(&build.out_dir), circuit_name);
+            output.built_circuits.push(BuiltCircuit {
+                name: circuit_name.to_string(),
+                cpp_path: path.clone(),
+                dat_path: circuit_dat.clone(),
+                runtime_version: circuit_runtime.version.to_string(),
+                lib_path: stamp.with_extension(""),
+                dat_sha256,
+                metadata: CircuitMetadata::parse_cpp(&circuit_cpp),
+            });
+            let circuit_dat_dest = build
+                .witnesscalc_path
+                .join("src")
+                .join(format!("{}.dat", circuit_name));
+            // Enabling the `compressed` feature needs the compressed copy of the `.dat` file
+            let up_to_date = fs::read_to_string(&stamp).is_ok_and(|hash| hash == inputs_hash)
+                && stamp.with_extension("").exists()
+                && circuit_dat_dest.with_extension("meta").exists()
+                && (!cfg!(feature = "compressed")
+                    || circuit_dat_dest.with_extension("dat.deflate").exists());
+            let cpp = if up_to_date {
+                None
+            } else {
+                write_circuit_data(&circuit_dat_dest, &circuit_cpp, &circuit_dat_bytes)?;
+                //For each .cpp file, do the following: find the last include statement (should be #include "calcwit.hpp") and insert the following on the next line: namespace CIRCUIT_NAME {. Then, insert the closing } at the end of the file:
+                let circuit_cpp = circuit_cpp.replace(
+                    "#include \"calcwit.hpp\"",
+                    "#include \"calcwit.hpp\"\nnamespace CIRCUIT_NAME {",
+                );
+                Some(circuit_cpp + "\n}")
+            };
+
+            runtime_circuits
+                .iter_mut()
+                .find(|(runtime, _)| runtime.version == circuit_runtime.version)
+                .unwrap()
+                .1
+                .push(CircuitBuild {
+                    path: path.clone(),
+                    inputs_hash,
+                    cpp,
+                });
+        }
+        Ok(runtime_circuits)
+    }
+
+    /// Builds the libraries of `runtime` and of its circuits, renaming their symbols unless it
+    /// is the primary runtime, and its baseline variant for CPU dispatch.
+    fn build_runtime(
+        &self,
+        build: &SourceBuild,
+        runtime: &'static runtime::Runtime,
+        circuits: &[CircuitBuild],
+        output: &mut SourceBuildOutput,
+        warnings: &mut Warnings,
+    ) -> Result<(), BuildError> {
+        let runtime_path = runtime.tree(&build.out_dir);
+        self.prepare_runtime_tree(build, runtime, !circuits.is_empty(), warnings)?;
+        self.compile_runtime(build, runtime, circuits, output, warnings)?;
+
+        let circuit_files = circuits
+            .iter()
+            .map(|circuit| circuit.path.clone())
+            .collect::<Vec<_>>();
+        self.link_circuits(&circuit_files);
+        let runtime_lib_dir = runtime_path.join("package").join("lib");
+        for path in &circuit_files {
+            let circuit_name = path.file_stem().unwrap().to_str().unwrap();
+            let header = format!("witnesscalc_{}.h", circuit_name);
+            output.bundle_files.extend([
+                bundle_lib(&runtime_lib_dir, &format!("witnesscalc_{}", circuit_name)),
+                (
+                    runtime_path.join("src").join(&header),
+                    format!("include/{}", header),
+                ),
+                (path.with_extension("dat"), format!("{}.dat", circuit_name)),
+            ]);
+        }
+
+        if !std::ptr::eq(runtime, build.primary) {
+            // Every runtime has its own fr library. Give this one, and the circuits built
+            // against it, private symbol names so they can be linked next to the primary
+            // runtime's. GMP is only linked once.
+            let fr_lib = runtime.fr_lib(build.primary);
+            fs::copy(
+                runtime_lib_dir.join("libfr.a"),
+                runtime_lib_dir.join(format!("lib{}.a", fr_lib)),
+            )
+            .map_err(BuildError::io("Failed to copy the fr library"))?;
+            let circuit_libs = circuit_files
+                .iter()
+                .map(|path| {
+                    format!(
+                        "witnesscalc_{}",
+                        path.file_stem().unwrap().to_str().unwrap()
+                    )
+                })
+                .collect::<Vec<_>>();
+            let mut libs = circuit_libs.clone();
+            libs.push(fr_lib.clone());
+            symbols::prefix_symbols(
+                &build.symbol_tools,
+                &runtime_lib_dir,
+                &libs,
+                &format!("witnesscalc_{}_", runtime.ident()),
+                &circuit_libs,
+                "",
+            )?;
+            self.cargo(&format!("rustc-link-lib=static={}", fr_lib));
+            output
+                .bundle_files
+                .push(bundle_lib(&runtime_lib_dir, &fr_lib));
+            output.runtime_lib_dirs.push(runtime_lib_dir);
+        }
+
+        if build.cpu_dispatch {
+            let baseline_path = runtime.baseline_tree(&build.out_dir);
+            for (circuit_name, compile_time) in self.build_baseline_variant(
+                runtime,
+                build.primary,
+                &runtime_path,
+                &baseline_path,
+                &circuit_files,
+                &build.target_config,
+                build.toolchain.as_ref(),
+                &build.symbol_tools,
+                warnings,
+            )? {
+                *output.compile_times.entry(circuit_name).or_default() += compile_time;
+            }
+            let baseline_lib_dir = baseline_path.join("package").join("lib");
+            for path in &circuit_files {
+                let circuit_name = path.file_stem().unwrap().to_str().unwrap();
+                output.bundle_files.push(bundle_lib(
+                    &baseline_lib_dir,
+                    &format!("witnesscalc_{}_baseline", circuit_name),
+                ));
+            }
+            output.bundle_files.push(bundle_lib(
+                &baseline_lib_dir,
+                &runtime.baseline_fr_lib(build.primary),
+            ));
+            output.runtime_lib_dirs.push(baseline_lib_dir);
+        }
+        Ok(())
+    }
+
+    /// Creates the source tree of `runtime` from the fetched sources, and checks out its
+    /// pinned revision.
+    fn prepare_runtime_tree(
+        &self,
+        build: &SourceBuild,
+        runtime: &runtime::Runtime,
+        has_circuits: bool,
+        warnings: &mut Warnings,
+    ) -> Result<(), BuildError> {
+        let runtime_path = runtime.tree(&build.out_dir);
+        if runtime.is_base() {
+            if has_circuits {
+                if let Some(commit) = self.revision(runtime.version, warnings) {
+                    source::pin_revision(&runtime_path, runtime.version, &commit, warnings)?;
+                }
+            }
+        } else {
+            let commit = self.revision(runtime.version, warnings);
+            if runtime_path.exists() {
+                if let Some(commit) = &commit {
+                    source::pin_revision(&runtime_path, runtime.version, commit, warnings)?;
+                }
+            } else {
+                source::create_runtime_tree(
+                    &build.witnesscalc_path,
+                    &runtime_path,
+                    runtime.version,
+                    self.version_source(runtime.version).as_deref(),
+                    commit.as_deref(),
+                    &build.target_config.gmp_lib_folder,
+                    warnings,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles the circuits of `runtime` that are not up to date, and its fr library unless
+    /// it is built.
+    fn compile_runtime(
+        &self,
+        build: &SourceBuild,
+        runtime: &runtime::Runtime,
+        circuits: &[CircuitBuild],
+        output: &mut SourceBuildOutput,
+        warnings: &mut Warnings,
+    ) -> Result<(), BuildError> {
+        let runtime_path = runtime.tree(&build.out_dir);
+        let mut outdated_files = Vec::new();
+        for circuit in circuits {
+            let Some(circuit_cpp) = &circuit.cpp else {
+                continue;
+            };
+            let circuit_name = circuit.path.file_stem().unwrap().to_str().unwrap();
+            let circuit_cpp_dest = runtime_path
+                .join("src")
+                .join(circuit_name)
+                .with_extension("cpp");
+            fs::write(&circuit_cpp_dest, circuit_cpp)
+                .map_err(BuildError::io("Failed to write circuit .cpp file"))?;
+            // Drop the previous library, so it can't be mistaken for a fresh one, and any
+            // library left in another runtime's tree, which would shadow this one
+            for other_runtime in runtime::RUNTIMES {
+                let baseline_lib_dir = other_runtime
+                    .baseline_tree(&build.out_dir)
+                    .join("package")
+                    .join("lib");
+                let stale_libs = [
+                    circuit_stamp(&other_runtime.tree(&build.out_dir), circuit_name)
+                        .with_extension(""),
+                    baseline_lib_dir.join(format!("libwitnesscalc_{}.a", circuit_name)),
+                    baseline_lib_dir.join(format!("libwitnesscalc_{}_baseline.a", circuit_name)),
+                ];
+                for stale_lib in stale_libs.iter().filter(|lib| lib.exists()) {
+                    fs::remove_file(stale_lib)
+                        .map_err(BuildError::io("Failed to remove a stale circuit library"))?;
+                }
+            }
+            outdated_files.push(circuit.path.clone());
+        }
+        let fr_built = runtime_path
+            .join("package")
+            .join("lib")
+            .join("libfr.a")
+            .exists();
+        if !outdated_files.is_empty() || !fr_built {
+            self.write_templates(&outdated_files, &runtime_path)?;
+            output.compile_times.extend(match build.backend {
+                Backend::Make => {
+                    if let Some(external_gmp) = &build.external_gmp {
+                        external_gmp.install(&runtime_path, &build.target_config.gmp_lib_folder)?;
+                    }
+                    self.build_for_circuits_with_different_versions(
+                        &outdated_files,
+                        &runtime_path,
+                        &build.target_config,
+                        build.toolchain.as_ref(),
+                        build.portable,
+                        warnings,
+                    )?
+                }
+                Backend::Cc => {
+                    let cc_config = cc_backend::CcConfig {
+                        target: &build.target,
+                        host: &build.host,
+                        cxx_flags: &self.cxx_flags,
+                        gmp_include: match &build.external_gmp {
+                            Some(external_gmp) => external_gmp.include_dir.clone(),
+                            None => Some(build.bundled_gmp_dir().join("include")),
+                        },
+                    };
+                    // The fr library does not depend on the circuits, so it is shared
+                    // through the build cache. The CMake build compiles it with them.
+                    let mut build_fr = !fr_built;
+                    if let Some(cache_entry) = self
+                        .cache_entry(&build.target, build.gmp == Gmp::Bundled, build.fat_gmp)
+                        .filter(|_| build_fr)
+                    {
+                        cache_entry.install_lib(
+                            "fr",
+                            &format!(
+                                "{}\n{:?}",
+                                build.build_options[runtime.version], cc_config.gmp_include
+                            ),
+                            &runtime_path.join("package").join("lib"),
+                            |lib_dir| cc_backend::compile_fr(&runtime_path, lib_dir, &cc_config),
+                        )?;
+                        build_fr = false;
+                    }
+                    cc_backend::compile(
+                        &runtime_path,
+                        &outdated_files
+                            .iter()
+                            .map(|path| path.file_stem().unwrap().to_str().unwrap())
+                            .collect::<Vec<_>>(),
+                        build_fr,
+                        &cc_config,
+                    )?
+                }
+            });
+        }
+        for circuit in circuits.iter().filter(|circuit| circuit.cpp.is_some()) {
+            let circuit_name = circuit.path.file_stem().unwrap().to_str().unwrap();
+            fs::write(
+                circuit_stamp(&runtime_path, circuit_name),
+                &circuit.inputs_hash,
+            )
+            .map_err(BuildError::io("Failed to write the circuit build stamp"))?;
+        }
+        Ok(())
+    }
+
+    /// Links the libraries shared by the circuits, and adds the directories of every runtime
+    /// to the linker search path.
+    fn link_source_build(&self, build: &SourceBuild, runtime_lib_dirs: &[PathBuf]) {
+        if build.cpu_dispatch {
+            self.cargo("rustc-cfg=witnesscalc_cpu_dispatch");
+        }
+
+        self.link_cxx_stdlib();
+        // Link the gmp and fr libraries. The GMP of gmp-mpfr-sys is linked by that crate.
+        if build.gmp == Gmp::Bundled {
+            if build.backend == Backend::Cc {
+                self.cargo(&format!(
+                    "rustc-link-search=native={}",
+                    build.bundled_gmp_dir().join("lib").display()
+                ));
+            }
+            self.cargo("rustc-link-lib=static=gmp");
+        }
+        self.cargo("rustc-link-lib=static=fr");
+        if let (Gmp::System, Some(external_gmp)) = (build.gmp, &build.external_gmp) {
+            for link_path in &external_gmp.link_paths {
+                self.cargo(&format!("rustc-link-search=native={}", link_path.display()));
+            }
+            self.cargo("rustc-link-lib=gmp");
+        }
+        // Specify the path to the witnesscalc library for the linker
+        self.cargo(&format!(
+            "rustc-link-search=native={}",
+            build.lib_dir().to_string_lossy()
+        ));
+        for runtime_lib_dir in runtime_lib_dirs {
+            self.cargo(&format!(
+                "rustc-link-search=native={}",
+                runtime_lib_dir.to_string_lossy()
+            ));
+        }
+    }
+
+    /// Exports the built libraries to a bundle `prebuilt_dir` can link.
+    fn export_source_build(
+        &self,
+        build: &SourceBuild,
+        export_bundle: &Path,
+        runtime_circuits: &[(&'static runtime::Runtime, Vec<CircuitBuild>)],
+        mut bundle_files: Vec<(PathBuf, String)>,
+        manifest_circuits: BTreeMap<String, ManifestCircuit>,
+    ) -> Result<(), BuildError> {
+        let lib_dir = build.lib_dir();
+        bundle_files.push(bundle_lib(&lib_dir, "fr"));
+        // Installed next to the other libraries by the witnesscalc CMake build
+        match (build.gmp, build.backend) {
+            (Gmp::Bundled, Backend::Make) => bundle_files.push(bundle_lib(&lib_dir, "gmp")),
+            (Gmp::Bundled, Backend::Cc) => {
+                bundle_files.push(bundle_lib(&build.bundled_gmp_dir().join("lib"), "gmp"))
+            }
+            _ => {}
+        }
+        let manifest = Manifest {
+            path: export_bundle.to_path_buf(),
+            target: build.target.clone(),
+            witnesscalc_revisions: runtime_circuits
+                .iter()
+                .filter(|(_, circuits)| !circuits.is_empty())
+                .map(|(runtime, _)| {
+                    (
+                        runtime.version.to_string(),
+                        self.pinned_revision(runtime.version),
+                    )
+                })
+                .collect(),
+            cpu_dispatch: build.cpu_dispatch,
+            circuits: manifest_circuits,
+        };
+        prebuilt::export(
+            export_bundle,
+            &build.out_dir.join("witnesscalc-bundle"),
+            &bundle_files,
+            &manifest,
+        )?;
+        println!("Exported the circuits to {}", export_bundle.display());
+        Ok(())
+    }
+
+    /// Links the prebuilt libraries in `prebuilt_dir` after checking them against its
+    /// manifest, and writes the circuit data `witness!` embeds to `witnesscalc_path`.
+    fn link_prebuilt(
+        &self,
+        prebuilt_dir: &Path,
+        target: &str,
+        circuit_files: &[PathBuf],
+        witnesscalc_path: &Path,
+    ) -> Result<BuildOutput, BuildError> {
+        self.cargo(&format!(
+            "rerun-if-changed={}",
+            prebuilt_dir.join(prebuilt::MANIFEST_NAME).display()
+        ));
+        let manifest = Manifest::read(prebuilt_dir)?;
+        let mut circuit_sources = Vec::new();
+        for path in circuit_files {
+            let circuit_name = path.file_stem().unwrap().to_str().unwrap();
+            let circuit_dat = path.with_extension("dat");
+            self.cargo(&format!("rerun-if-changed={}", path.display()));
+            self.cargo(&format!("rerun-if-changed={}", circuit_dat.display()));
+            let circuit_cpp = fs::read_to_string(path).map_err(BuildError::io(format!(
+                "Failed to read circuit .cpp file {}",
+                path.display()
+            )))?;
+            let circuit_dat_bytes = fs::read(&circuit_dat).map_err(BuildError::io(format!(
+                "Failed to read circuit .dat file {}",
+                circuit_dat.display()
+            )))?;
+            circuit_sources.push((circuit_name, circuit_cpp, circuit_dat_bytes));
+        }
+        let circuits = manifest.verify(
+            target,
+            &circuit_sources
+                .iter()
+                .map(|(name, cpp, dat)| (*name, cpp.as_str(), dat.as_slice()))
+                .collect::<Vec<_>>(),
+            |version| self.pinned_revision(version),
+        )?;
+
+        let src_dir = witnesscalc_path.join("src");
+        fs::create_dir_all(&src_dir).map_err(BuildError::io(
+            "Failed to create the circuit data directory",
+        ))?;
+        fs::write(witnesscalc_path.join(SOURCE_STAMP), "prebuilt")
+            .map_err(BuildError::io("Failed to mark the prebuilt circuit data"))?;
+        for (circuit_name, circuit_cpp, circuit_dat_bytes) in &circuit_sources {
+            write_circuit_data(
+                &src_dir.join(format!("{}.dat", circuit_name)),
+                circuit_cpp,
+                circuit_dat_bytes,
+            )?;
+        }
+
+        // Same order as a build: circuits, their runtimes' fr libraries, then the shared ones
+        let mut libs = circuit_sources
+            .iter()
+            .map(|(circuit_name, _, _)| format!("witnesscalc_{}", circuit_name))
+            .collect::<Vec<_>>();
+        let mut runtimes = Vec::<&runtime::Runtime>::new();
+        for circuit in &circuits {
+            if !runtimes
+                .iter()
+                .any(|runtime| std::ptr::eq(*runtime, circuit.runtime))
+            {
+                runtimes.push(circuit.runtime);
+            }
+        }
+        let primary = runtime::primary(&runtimes);
+        for runtime in runtimes
+            .iter()
+            .filter(|runtime| !std::ptr::eq(**runtime, primary))
+        {
+            libs.push(runtime.fr_lib(primary));
+        }
+        if manifest.cpu_dispatch {
+            for (circuit_name, _, _) in &circuit_sources {
+                libs.push(format!("witnesscalc_{}_baseline", circuit_name));
+            }
+            for runtime in &runtimes {
+                libs.push(runtime.baseline_fr_lib(primary));
+            }
+        }
+        libs.push("fr".to_string());
+        let gmp = self.resolve_gmp(Backend::Make)?;
+        if gmp == Gmp::Bundled {
+            libs.push("gmp".to_string());
+        }
+        let missing = libs
+            .iter()
+            .map(|lib| format!("lib{}.a", lib))
+            .filter(|lib| !prebuilt_dir.join(lib).exists())
+            .collect::<Vec<_>>();
+        if !missing.is_empty() {
+            return Err(BuildError::Config(format!(
+                "Missing prebuilt libraries in {}: {}",
+                prebuilt_dir.display(),
+                missing.join(", ")
+            )));
+        }
+
+        self.cargo(&format!(
+            "rustc-link-search=native={}",
+            prebuilt_dir.display()
+        ));
+        for lib in &libs {
+            self.cargo(&format!(
+                "rerun-if-changed={}",
+                prebuilt_dir.join(format!("lib{}.a", lib)).display()
+            ));
+            self.cargo(&format!("rustc-link-lib=static={}", lib));
+        }
+        if gmp != Gmp::Bundled {
+            let external_gmp = ExternalGmp::probe(gmp, self.cargo_metadata)?;
+            // The GMP of gmp-mpfr-sys is linked by that crate
+            if gmp == Gmp::System {
+                for link_path in &external_gmp.link_paths {
+                    self.cargo(&format!("rustc-link-search=native={}", link_path.display()));
+                }
+                self.cargo("rustc-link-lib=gmp");
+            }
+        }
+        self.link_cxx_stdlib();
+        if manifest.cpu_dispatch {
+            self.cargo("rustc-cfg=witnesscalc_cpu_dispatch");
+        }
+
+        Ok(BuildOutput {
+            circuits: circuit_sources
+                .iter()
+                .zip(&circuits)
+                .zip(circuit_files)
+                .map(
+                    |(((circuit_name, circuit_cpp, _), circuit), path)| BuiltCircuit {
+                        name: circuit_name.to_string(),
+                        cpp_path: path.clone(),
+                        dat_path: path.with_extension("dat"),
+                        runtime_version: circuit.runtime.version.to_string(),
+                        lib_path: prebuilt_dir.join(format!("libwitnesscalc_{}.a", circuit_name)),
+                        dat_sha256: circuit.dat_sha256.clone(),
+                        metadata: CircuitMetadata::parse_cpp(circuit_cpp),
+                    },
+                )
+                .collect(),
+            lib_dir: prebuilt_dir.to_path_buf(),
+            compile_times: BTreeMap::new(),
+            warnings: Vec::new(),
+        })
+    }
+
+    /// Links the C++ standard library the witnesscalc runtime is written against.
+    fn link_cxx_stdlib(&self) {
+        // This is necessary for Rust tests to run on the host, non-host targets may require a
+        // specific way of linking (e.g., through linking flags in xcode)
+        #[cfg(target_os = "macos")]
+        {
+            self.cargo("rustc-link-lib=c++"); // macOS default
+        }
+        #[cfg(not(target_os = "macos"))]
+        {
+            self.cargo("rustc-link-lib=stdc++"); // Linux or other platforms
+        }
+    }
+
+    /// Builds the circuits of `runtime` with the portable field arithmetic in a copy of its
+    /// tree, as the baseline variant for CPU dispatch, and links them with their symbols
+    /// renamed so they do not clash with the optimised variant.
+    #[allow(clippy::too_many_arguments)]
+    fn build_baseline_variant(
+        &self,
+        runtime: &runtime::Runtime,
+        primary: &runtime::Runtime,
+        runtime_path: &Path,
+        baseline_path: &Path,
+        circuit_files: &[PathBuf],
+        target_config: &target::TargetConfig,
+        toolchain: Option<&cross::Toolchain>,
+        symbol_tools: &symbols::SymbolTools,
+        warnings: &mut Warnings,
+    ) -> Result<BTreeMap<String, Duration>, BuildError> {
+        if !baseline_path.exists() {
+            source::create_variant_tree(runtime_path, baseline_path)?;
+        }
+        let lib_dir = baseline_path.join("package").join("lib");
+        // The stale libraries of circuits rebuilt in the optimised tree were removed
+        let outdated_files = circuit_files
+            .iter()
+            .filter(|path| {
+                let circuit_name = path.file_stem().unwrap().to_str().unwrap();
+                !lib_dir
+                    .join(format!("libwitnesscalc_{}.a", circuit_name))
+                    .exists()
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut compile_times = BTreeMap::new();
+        if !outdated_files.is_empty() || !lib_dir.join("libfr.a").exists() {
+            for path in &outdated_files {
+                let circuit_name = path.file_stem().unwrap().to_str().unwrap();
+                for file in [
+                    format!("{}.cpp", circuit_name),
+                    format!("witnesscalc_{}.cpp", circuit_name),
+                    format!("witnesscalc_{}.h", circuit_name),
+                ] {
+                    fs::copy(
+                        runtime_path.join("src").join(&file),
+                        baseline_path.join("src").join(&file),
+                    )
+                    .map_err(BuildError::io(format!("Failed to copy {}", file)))?;
+                }
+            }
+            compile_times = self.build_for_circuits_with_different_versions(
+                &outdated_files,
+                baseline_path,
+                target_config,
+                toolchain,
+                true,
+                warnings,
+            )?;
+        }
+
+        // Copies of the built libraries are renamed, so the originals tell what is up to date
+        let fr_lib = runtime.baseline_fr_lib(primary);
+        fs::copy(
+            lib_dir.join("libfr.a"),
+            lib_dir.join(format!("lib{}.a", fr_lib)),
+        )
+        .map_err(BuildError::io("Failed to copy the fr library"))?;
+        let mut libs = Vec::new();
+        let mut entry_points = Vec::new();
+        for path in circuit_files {
+            let circuit_name = path.file_stem().unwrap().to_str().unwrap();
+            let lib = format!("witnesscalc_{}_baseline", circuit_name);
+            fs::copy(
+                lib_dir.join(format!("libwitnesscalc_{}.a", circuit_name)),
+                lib_dir.join(format!("lib{}.a", lib)),
+            )
+            .map_err(BuildError::io("Failed to copy a circuit library"))?;
+            libs.push(lib);
+            entry_points.push(format!("witnesscalc_{}", circuit_name));
+        }
+        // Linked after the circuits using it
+        libs.push(fr_lib.clone());
+        // `witnesscalc_<circuit>` becomes `witnesscalc_<circuit>_baseline`
+        symbols::prefix_symbols(
+            symbol_tools,
+            &lib_dir,
+            &libs,
+            &format!("{}_", fr_lib.replacen("fr", "witnesscalc", 1)),
+            &entry_points,
+            "_baseline",
+        )?;
+        for lib in &libs {
+            self.cargo(&format!("rustc-link-lib=static={}", lib));
+        }
+        Ok(compile_times)
+    }
+
+    fn write_templates(
+        &self,
+        circuit_files: &[PathBuf],
+        witnesscalc_path: &Path,
+    ) -> Result<(), BuildError> {
+        for path in circuit_files {
+            let circuit_name = path.file_stem().unwrap().to_str().unwrap();
+            //Find a witnesscalc_template.cpp template file in the src. Replace all the @CIRCUIT_NAME@ inside it with the circuit name and write it to the src directory, replacing "template" in the name with the circuit name
+            let template_path = witnesscalc_path
+                .join("src")
+                .join("witnesscalc_template.cpp");
+            let template = fs::read_to_string(&template_path)
+                .map_err(BuildError::io("Failed to read template file"))?;
+            let template = template.replace("@CIRCUIT_NAME@", circuit_name);
+            let template_dest = witnesscalc_path
+                .join("src")
+                .join(format!("witnesscalc_{}.cpp", circuit_name));
+            fs::write(&template_dest, template)
+                .map_err(BuildError::io("Failed to write the templated .cpp file"))?;
+            //Find a witnesscalc_template.h template file in the src. Replace all the @CIRCUIT_NAME@ inside it with the circuit name, @CIRCUIT_NAME_CAPS@ with the capitalized name, and write it to the src directory, replacing "template" in the name with the circuit name
+            let template_path = witnesscalc_path.join("src").join("witnesscalc_template.h");
+            let template = fs::read_to_string(&template_path)
+                .map_err(BuildError::io("Failed to read template file"))?;
+            let template = template
+                .replace("@CIRCUIT_NAME@", circuit_name)
+                .replace("@CIRCUIT_NAME_CAPS@", &circuit_name.to_uppercase());
+            let template_dest = witnesscalc_path
+                .join("src")
+                .join(format!("witnesscalc_{}.h", circuit_name));
+            fs::write(&template_dest, template)
+                .map_err(BuildError::io("Failed to write the templated .h file"))?;
+        }
+
+        Ok(())
+    }
+
+    fn build_for_circuits_with_different_versions(
+        &self,
+        circuit_files: &[PathBuf],
+        witnesscalc_path: &Path,
+        target_config: &target::TargetConfig,
+        toolchain: Option<&cross::Toolchain>,
+        portable: bool,
+        warnings: &mut Warnings,
+    ) -> Result<BTreeMap<String, Duration>, BuildError> {
+        //the circuit name list would look like "circuit1;circuit2;circuit3"
+        let circuit_names = circuit_files
+            .iter()
+            .map(|path| path.file_stem().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+
+        let circuit_names_semicolon = circuit_names.join(";");
+
+        let mut make = Command::new("make");
+        make.env("CIRCUIT_NAMES", circuit_names_semicolon)
+            .arg(&target_config.make_target)
+            .current_dir(witnesscalc_path);
+        // Compile the circuits in parallel
+        self.configure_jobs(&mut make);
+        // Settings for the CMake runs of make, read by CMake 3.21 and later as a toolchain file
+        let mut cmake_settings = String::new();
+        if let (Some(toolchain), Some(system_processor)) =
+            (toolchain, target_config.system_processor())
+        {
+            cmake_settings.push_str(&toolchain.cmake_settings(system_processor));
+            toolchain.make_env(&mut make);
+        }
+        if portable {
+            cmake_settings.push_str("set(USE_ASM OFF CACHE BOOL \"\" FORCE)\n");
+        }
+        if !cmake_settings.is_empty() {
+            let cmake_settings_path = witnesscalc_path.join("witnesscalc-settings.cmake");
+            fs::write(&cmake_settings_path, &cmake_settings)
+                .map_err(BuildError::io("Failed to write the CMake settings"))?;
+            make.env("CMAKE_TOOLCHAIN_FILE", &cmake_settings_path);
+        }
+        let cxx_flags = self.cxx_flags_env().or_else(|| env::var("CXXFLAGS").ok());
+        if let Some(cxx_flags) = &cxx_flags {
+            // Picked up by CMake as the initial CMAKE_CXX_FLAGS
+            make.env("CXXFLAGS", cxx_flags);
+        }
+        // CMake only reads the settings and CXXFLAGS when it configures a build directory, so
+        // the directories configured with other ones are configured again
+        let cmake_options = format!("{}{:?}", cmake_settings, cxx_flags);
+        let cmake_options_path = witnesscalc_path.join(CMAKE_OPTIONS_STAMP);
+        if fs::read_to_string(&cmake_options_path).ok().as_deref() != Some(cmake_options.as_str()) {
+            remove_cmake_build_dirs(witnesscalc_path)?;
+            fs::write(&cmake_options_path, cmake_options)
+                .map_err(BuildError::io("Failed to write the CMake options"))?;
+        }
+        // CMake reports the steps of each circuit library's target, with its object directory,
+        // and the target once it is built. The circuits are built in parallel, so each is timed
+        // from its first step, or the start of make if its steps are not reported
+        let started = Instant::now();
+        let mut circuit_started = HashMap::new();
+        let mut compile_times = BTreeMap::new();
+        let make_process = error::output_lines(&mut make, |line| {
+            if let Some(target) = line.split("Built target witnesscalc_").nth(1) {
+                if let Some(circuit_name) =
+                    circuit_names.iter().find(|name| **name == target.trim())
+                {
+                    let circuit_started = circuit_started.get(circuit_name).unwrap_or(&started);
+                    compile_times
+                        .entry(circuit_name.to_string())
+                        .or_insert_with(|| circuit_started.elapsed());
+                }
+            } else if let Some(circuit_name) = circuit_names
+                .iter()
+                .find(|name| line.contains(&format!("witnesscalc_{}.dir/", name)))
+            {
+                circuit_started
+                    .entry(*circuit_name)
+                    .or_insert_with(Instant::now);
+            }
+        })?;
+        for circuit_name in &circuit_names {
+            let compile_time = *compile_times
+                .entry(circuit_name.to_string())
+                .or_insert_with(|| started.elapsed());
+            println!("Compiled circuit {} in {:.2?}", circuit_name, compile_time);
+        }
+
+        if let Err(make_error) = error::check(&make, &make_process) {
+            // Check if any of the required libraries were actually built despite the error
+            let lib_dir = witnesscalc_path.join("package").join("lib");
+            let all_libs_exist = circuit_names.iter().all(|circuit_name| {
+                lib_dir
+                    .join(format!("libwitnesscalc_{}.a", circuit_name))
+                    .exists()
+            });
+
+            if !all_libs_exist {
+                return Err(make_error);
+            }
+            warnings.warn(format!(
+                "Make command failed but required libraries exist. Continuing... ({})",
+                make_error.to_string().lines().next().unwrap_or_default()
+            ));
+        }
+
+        Ok(compile_times)
+    }
+
+    fn link_circuits(&self, circuit_files: &[PathBuf]) {
+        let circuit_names = circuit_files
+            .iter()
+            .map(|path| path.file_stem().unwrap().to_str().unwrap())
+            .collect::<Vec<_>>();
+        // Link the witnesscalc library for the circuit
+        circuit_names.iter().for_each(|circuit_name| {
+            self.cargo(&format!(
+                "rustc-link-lib=static=witnesscalc_{}",
+                circuit_name
+            ));
+        });
+    }
+}
+
+/// A circuit's path, `.cpp` source, `.dat` file and the runtime it is built against.
+type CircuitSource = (PathBuf, String, Vec<u8>, &'static runtime::Runtime);
+
+/// Settings of a build from source, resolved once and shared by its steps.
+struct SourceBuild {
+    target: String,
+    host: String,
+    out_dir: PathBuf,
+    /// Tree of the base runtime, the sources are fetched to
+    witnesscalc_path: PathBuf,
+    backend: Backend,
+    gmp: Gmp,
+    external_gmp: Option<ExternalGmp>,
+    portable: bool,
+    cpu_dispatch: bool,
+    /// Whether the bundled GMP is built for every CPU of the target, for CPU dispatch
+    fat_gmp: bool,
+    target_config: target::TargetConfig,
+    toolchain: Option<cross::Toolchain>,
+    symbol_tools: symbols::SymbolTools,
+    /// The runtime whose libraries keep their symbol names
+    primary: &'static runtime::Runtime,
+    /// Options the circuits of each runtime are built with, by runtime version
+    build_options: HashMap<&'static str, String>,
+}
+
+impl SourceBuild {
+    /// Directory of the libraries of the primary runtime.
+    fn lib_dir(&self) -> PathBuf {
+        self.primary.tree(&self.out_dir).join("package").join("lib")
+    }
+
+    /// Directory `build_gmp.sh` installs the bundled GMP to.
+    fn bundled_gmp_dir(&self) -> PathBuf {
+        self.witnesscalc_path
+            .join("depends")
+            .join("gmp")
+            .join(&self.target_config.gmp_lib_folder)
+    }
+}
+
+/// What the steps of a build from source produced.
+#[derive(Default)]
+struct SourceBuildOutput {
+    built_circuits: Vec<BuiltCircuit>,
+    compile_times: BTreeMap<String, Duration>,
+    /// What an exported bundle is made of: files with their path in the bundle
+    bundle_files: Vec<(PathBuf, String)>,
+    manifest_circuits: BTreeMap<String, ManifestCircuit>,
+    /// Library directories of the other runtimes and of the baseline variants
+    runtime_lib_dirs: Vec<PathBuf>,
+}
+
+/// A circuit to link, and to compile unless it is up to date.
+struct CircuitBuild {
+    path: PathBuf,
+    /// Hash of the circuit sources and build options
+    inputs_hash: String,
+    /// The namespaced `.cpp` source to compile, `None` if the library is up to date
+    cpp: Option<String>,
+}
+
+/// Writes the `.dat` file of a circuit to `circuit_dat_dest`, along with the files `witness!`
+/// reads next to it: the hash of the `.dat` file, its compressed copy and the circuit sizes.
+fn write_circuit_data(
+    circuit_dat_dest: &Path,
+    circuit_cpp: &str,
+    circuit_dat_bytes: &[u8],
+) -> Result<(), BuildError> {
+    fs::write(circuit_dat_dest, circuit_dat_bytes).map_err(BuildError::io(format!(
+        "Failed to write circuit .dat file {}",
+        circuit_dat_dest.display()
+    )))?;
+    // Record the .dat hash so circuit data provided at runtime can be checked against it
+    fs::write(
+        circuit_dat_dest.with_extension("dat.sha256"),
+        circuit::sha256_hex(circuit_dat_bytes),
+    )
+    .map_err(BuildError::io("Failed to write circuit .dat hash"))?;
+    #[cfg(feature = "compressed")]
+    fs::write(
+        circuit_dat_dest.with_extension("dat.deflate"),
+        circuit::compress_circuit_data(circuit_dat_bytes)
+            .map_err(BuildError::io("Failed to compress circuit .dat file"))?,
+    )
+    .map_err(BuildError::io(
+        "Failed to write compressed circuit .dat file",
+    ))?;
+    // Record the circuit sizes for `WitnessCalculator::METADATA`
+    fs::write(
+        circuit_dat_dest.with_extension("meta"),
+        format!("{:?}", CircuitMetadata::parse_cpp(circuit_cpp).to_raw()),
+    )
+    .map_err(BuildError::io("Failed to write circuit metadata"))?;
+    Ok(())
+}
+
+/// Records the CMake settings and `CXXFLAGS` the `build_*` directories of a witnesscalc tree
+/// were configured with.
+const CMAKE_OPTIONS_STAMP: &str = "witnesscalc-cmake.options";
+
+/// Removes the `build_*` CMake build directories of a witnesscalc tree.
+fn remove_cmake_build_dirs(witnesscalc_path: &Path) -> Result<(), BuildError> {
+    let entries = fs::read_dir(witnesscalc_path)
+        .map_err(BuildError::io("Failed to read the witnesscalc tree"))?;
+    for entry in entries {
+        let path = entry
+            .map_err(BuildError::io("Failed to read the witnesscalc tree"))?
+            .path();
+        if path.is_dir()
+            && path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("build_"))
+        {
+            fs::remove_dir_all(&path).map_err(BuildError::io(format!(
+                "Failed to remove the CMake build directory {}",
+                path.display()
+            )))?;
+        }
+    }
+    Ok(())
+}
+
+/// A static library of `lib_dir` and its path in an exported bundle.
+fn bundle_lib(lib_dir: &Path, lib: &str) -> (PathBuf, String) {
+    let file_name = format!("lib{}.a", lib);
+    (lib_dir.join(&file_name), file_name)
+}
+
+/// Records where the witnesscalc trees in `OUT_DIR` were fetched from, or `prebuilt` when the
+/// `witnesscalc` directory only holds the circuit data of a prebuilt build.
+const SOURCE_STAMP: &str = ".source";
+
+/// Records the inputs hash of the library a circuit was last compiled into.
+fn circuit_stamp(runtime_path: &Path, circuit_name: &str) -> PathBuf {
+    runtime_path
+        .join("package")
+        .join("lib")
+        .join(format!("libwitnesscalc_{}.a.sha256", circuit_name))
+}
+
+/// Name of the file in `OUT_DIR` [`Build::compile`] writes its [`BuildOutput`] to.
+const OUTPUT_NAME: &str = "witnesscalc-build.json";
+
+/// What [`Build::compile`] built.
+#[derive(Clone, Debug)]
+pub struct BuildOutput {
+    /// The compiled circuits, empty when the build was skipped
+    pub circuits: Vec<BuiltCircuit>,
+    /// Directory containing the built static libraries, empty when the build was skipped
+    pub lib_dir: PathBuf,
+    /// How long each circuit compiled by this build took. With the CMake build, this is the
+    /// time from the first step CMake reported for the circuit's library until it reported it
+    /// built, counted from the start of `make` when it reports none
+    pub compile_times: BTreeMap<String, Duration>,
+    /// Non-fatal issues, also reported as `cargo:warning=` lines
+    pub warnings: Vec<String>,
+}
+
+impl BuildOutput {
+    fn to_json(&self) -> String {
+        // Source paths are relative to the crate being built, unlike where the JSON is read
+        let absolute = |path: &Path| {
+            std::path::absolute(path)
+                .unwrap_or_else(|_| path.to_path_buf())
+                .to_string_lossy()
+                .into_owned()
+        };
+        let circuits = self
+            .circuits
+            .iter()
+            .map(|circuit| {
+                json!({
+                    "name": circuit.name,
+                    "cpp_path": absolute(&circuit.cpp_path),
+                    "dat_path": absolute(&circuit.dat_path),
+                    "runtime_version": circuit.runtime_version,
+                    "lib_path": circuit.lib_path.to_string_lossy(),
+                    "dat_sha256": circuit.dat_sha256,
+                    "metadata": {
+                        "witness_size": circuit.metadata.witness_size,
+                        "main_input_signal_start": circuit.metadata.main_input_signal_start,
+                        "main_input_signal_count": circuit.metadata.main_input_signal_count,
+                        "total_signal_count": circuit.metadata.total_signal_count,
+                        "component_count": circuit.metadata.component_count,
+                        "constant_count": circuit.metadata.constant_count,
+                        "io_map_size": circuit.metadata.io_map_size,
+                    },
+                })
+            })
+            .collect::<Vec<_>>();
+        let compile_times = self
+            .compile_times
+            .iter()
+            .map(|(name, time)| (name.clone(), json!(time.as_secs_f64())))
+            .collect::<serde_json::Map<_, _>>();
+        let output = json!({
+            "circuits": circuits,
+            "lib_dir": self.lib_dir.to_string_lossy(),
+            "compile_times_secs": compile_times,
+            "warnings": self.warnings,
+        });
+        serde_json::to_string_pretty(&output).unwrap() + "\n"
+    }
+}
+
+/// A circuit of a [`BuildOutput`].
+#[derive(Clone, Debug)]
+pub struct BuiltCircuit {
+    pub name: String,
+    /// The circuit's generated `.cpp` file, as found in the circuits directory
+    pub cpp_path: PathBuf,
+    /// The circuit's `.dat` file, next to the `.cpp` file
+    pub dat_path: PathBuf,
+    /// Version of the circom runtime the circuit was built against, e.g. `v2.2.0`
+    pub runtime_version: String,
+    /// Static library of the circuit
+    pub lib_path: PathBuf,
+    /// Hash of the `.dat` file, as checked by `witness!(<circuit name>, dat = runtime)`
+    pub dat_sha256: String,
+    pub metadata: CircuitMetadata,
+}
+
+/// Collects non-fatal build issues, reported to cargo as they happen.
+struct Warnings {
+    cargo_metadata: bool,
+    messages: Vec<String>,
+}
+
+impl Warnings {
+    fn warn(&mut self, message: String) {
+        if self.cargo_metadata {
+            println!("cargo:warning={}", message);
+        }
+        self.messages.push(message);
+    }
+}
+
+/// The jobserver cargo runs the build script with, if any.
+fn jobserver() -> Option<&'static jobserver::Client> {
+    static JOBSERVER: OnceLock<Option<jobserver::Client>> = OnceLock::new();
+    JOBSERVER
+        .get_or_init(|| {
+            // SAFETY: the file descriptors named in `CARGO_MAKEFLAGS` are inherited from
+            // cargo and only ever used through this client
+            unsafe { jobserver::Client::from_env() }
+        })
+        .as_ref()
+}
+
+/// `v2.2.0` -> `V2_2_0`, for environment variable names.
+fn version_env_suffix(version: &str) -> String {
+    version.to_uppercase().replace('.', "_")
+}
+
+/// Compiles every circuit in `circuits_dir` and links them, see [`Build`] for more options.
+///
+/// Panics with the [`BuildError`] if the build fails.
+pub fn build_and_link(circuits_dir: &str) -> BuildOutput {
+    match Build::new().circuits_dir(circuits_dir).compile() {
+        Ok(output) => output,
+        Err(e) => panic!("Failed to build witnesscalc circuits: {}", e),
+    }
+}
@@ -0,0 +1,139 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use fs4::fs_std::FileExt;
+
+use super::{error::BuildError, source};
+use crate::circuit::sha256_hex;
+
+/// An entry of the build cache shared by every crate building circuits, holding the fetched
+/// witnesscalc sources, with GMP built for one target unless an external GMP is used, and the
+/// `fr` libraries built by the `cc` backend.
+pub(super) struct CacheEntry {
+    path: PathBuf,
+}
+
+impl CacheEntry {
+    /// The entry under `root` for `target`, identified by everything that affects the cached
+    /// build: the witnesscalc `source` of every runtime (its revision when pinned), the C/C++
+    /// compiler and whether GMP is built in it, and built fat.
+    pub(super) fn new(
+        root: &Path,
+        target: &str,
+        source: &str,
+        with_gmp: bool,
+        fat_gmp: bool,
+    ) -> Self {
+        let mut key = [
+            env!("CARGO_PKG_VERSION").to_string(),
+            source.to_string(),
+            compiler_id(target),
+            format!("gmp={}", with_gmp),
+        ]
+        .join("\n");
+        if fat_gmp {
+            key.push_str("\nfat");
+        }
+        Self {
+            path: root.join(target).join(&sha256_hex(key.as_bytes())[..16]),
+        }
+    }
+
+    /// Copies the cached tree to `dest`, running `prepare` to populate the entry first unless
+    /// a previous build did.
+    ///
+    /// The entry is locked meanwhile, so concurrent builds wait for the first one to populate
+    /// it instead of building it again or reading it half-built.
+    pub(super) fn install(
+        &self,
+        dest: &Path,
+        prepare: impl FnOnce(&Path) -> Result<(), BuildError>,
+    ) -> Result<(), BuildError> {
+        let _lock = self.lock()?;
+        let tree = self.path.join("witnesscalc");
+        if !tree.exists() {
+            source::create_tree(&tree, prepare)?;
+        }
+        source::create_tree(dest, |staging| {
+            source::copy_sources(&tree, staging)
+                .map_err(BuildError::io("Failed to copy the cached witnesscalc tree"))
+        })
+    }
+
+    /// Copies the static library `lib` built with the options `key` to `lib_dir`, running
+    /// `build` to build it into the directory it is given first unless a previous build did.
+    ///
+    /// The entry is locked meanwhile, like with [`CacheEntry::install`].
+    pub(super) fn install_lib(
+        &self,
+        lib: &str,
+        key: &str,
+        lib_dir: &Path,
+        build: impl FnOnce(&Path) -> Result<(), BuildError>,
+    ) -> Result<(), BuildError> {
+        let _lock = self.lock()?;
+        let cached_dir = self
+            .path
+            .join("lib")
+            .join(&sha256_hex(format!("{}\n{}", lib, key).as_bytes())[..16]);
+        if !cached_dir.exists() {
+            fs::create_dir_all(cached_dir.parent().unwrap())
+                .map_err(BuildError::io("Failed to create the build cache directory"))?;
+            source::create_tree(&cached_dir, build)?;
+        }
+        let file_name = format!("lib{}.a", lib);
+        fs::create_dir_all(lib_dir)
+            .map_err(BuildError::io("Failed to create the library directory"))?;
+        fs::copy(cached_dir.join(&file_name), lib_dir.join(&file_name)).map_err(BuildError::io(
+            format!("Failed to copy the cached {}", file_name),
+        ))?;
+        Ok(())
+    }
+
+    /// Locks the entry until the returned file is dropped.
+    fn lock(&self) -> Result<fs::File, BuildError> {
+        fs::create_dir_all(&self.path).map_err(BuildError::io(format!(
+            "Failed to create the build cache directory {}",
+            self.path.display()
+        )))?;
+        let lock = fs::File::create(self.path.join(".lock"))
+            .map_err(BuildError::io("Failed to create the build cache lock"))?;
+        FileExt::lock_exclusive(&lock).map_err(BuildError::io("Failed to lock the build cache"))?;
+        Ok(lock)
+    }
+}
+
+/// Per-user cache directory used when `WITNESSCALC_CACHE_DIR` is not set.
+pub(super) fn default_dir() -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| dir.join("witnesscalc-adapter"))
+}
+
+/// Identifies the C and C++ compilers used for `target`, as the `cc` crate would pick them.
+fn compiler_id(target: &str) -> String {
+    let target_var = target.replace('-', "_");
+    ["CC", "CXX"]
+        .iter()
+        .map(|tool| {
+            let compiler = env::var(format!("{}_{}", tool, target_var))
+                .or_else(|_| env::var(tool))
+                .unwrap_or_else(|_| if *tool == "CC" { "cc" } else { "c++" }.to_string());
+            // The first line names the compiler and its version
+            let version = Command::new(&compiler)
+                .arg("--version")
+                .output()
+                .map(|output| {
+                    String::from_utf8_lossy(&output.stdout)
+                        .lines()
+                        .next()
+                        .unwrap_or_default()
+                        .to_string()
+                })
+                .unwrap_or_default();
+            format!("{}={} ({})", tool, compiler, version)
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
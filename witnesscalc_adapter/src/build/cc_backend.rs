@@ -0,0 +1,169 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::error::BuildError;
+
+/// Directories of a witnesscalc tree holding the runtime and field sources.
+const SOURCE_DIRS: &[&str] = &["src", "depends/ffiasm/c"];
+
+/// Runtime sources compiled into every circuit library, with the circuit's namespace.
+const RUNTIME_SOURCES: &[&str] = &["calcwit.cpp", "witnesscalc.cpp"];
+
+/// Field sources of the `fr` library. The portable C++ implementation is used instead of
+/// `fr.asm`, which needs nasm.
+const FR_SOURCES: &[&str] = &["fr.cpp", "fr_generic.cpp", "fr_raw_generic.cpp"];
+
+/// Include directories of a witnesscalc tree, besides [`SOURCE_DIRS`].
+const INCLUDE_DIRS: &[&str] = &["depends/json/single_include"];
+
+/// Compiler settings shared by every library.
+pub(super) struct CcConfig<'a> {
+    pub(super) target: &'a str,
+    pub(super) host: &'a str,
+    pub(super) cxx_flags: &'a [String],
+    /// Include directory of the GMP headers, unless they are in the default search path
+    pub(super) gmp_include: Option<PathBuf>,
+}
+
+/// Compiles the `fr` library, unless `build_fr` is false, and a library per circuit into
+/// `lib_dir` with the `cc` crate, instead of the witnesscalc CMake build.
+///
+/// The circuits' `.cpp` files and template wrappers must already be in `src/`. Circuits are
+/// compiled in parallel, within the job slots of cargo's jobserver.
+pub(super) fn compile(
+    witnesscalc_path: &Path,
+    circuit_names: &[&str],
+    build_fr: bool,
+    config: &CcConfig,
+) -> Result<BTreeMap<String, Duration>, BuildError> {
+    let lib_dir = witnesscalc_path.join("package").join("lib");
+    fs::create_dir_all(&lib_dir)
+        .map_err(BuildError::io("Failed to create the library directory"))?;
+    let obj_dir = witnesscalc_path.join("build_cc");
+
+    if build_fr {
+        compile_fr(witnesscalc_path, &lib_dir, config)?;
+    }
+
+    let runtime_sources = RUNTIME_SOURCES
+        .iter()
+        .map(|source| find_source(witnesscalc_path, source))
+        .collect::<Result<Vec<_>, _>>()?;
+    let compile_times = thread::scope(|scope| {
+        let handles = circuit_names
+            .iter()
+            .map(|circuit_name| {
+                let runtime_sources = &runtime_sources;
+                let lib_dir = &lib_dir;
+                let obj_dir = obj_dir.join(circuit_name);
+                scope.spawn(move || {
+                    let started = Instant::now();
+                    let src = witnesscalc_path.join("src");
+                    let mut circuit = new_build(witnesscalc_path, config);
+                    circuit
+                        .define("CIRCUIT_NAME", Some(*circuit_name))
+                        .file(src.join(format!("{}.cpp", circuit_name)))
+                        .file(src.join(format!("witnesscalc_{}.cpp", circuit_name)))
+                        .files(runtime_sources);
+                    compile_library(
+                        &mut circuit,
+                        &format!("witnesscalc_{}", circuit_name),
+                        &obj_dir,
+                        lib_dir,
+                    )?;
+                    Ok((circuit_name.to_string(), started.elapsed()))
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Result<BTreeMap<_, _>, BuildError>>()
+    })?;
+    for (circuit_name, compile_time) in &compile_times {
+        println!("Compiled circuit {} in {:.2?}", circuit_name, compile_time);
+    }
+    Ok(compile_times)
+}
+
+/// Compiles the `fr` library of a witnesscalc tree into `lib_dir`.
+pub(super) fn compile_fr(
+    witnesscalc_path: &Path,
+    lib_dir: &Path,
+    config: &CcConfig,
+) -> Result<(), BuildError> {
+    fs::create_dir_all(lib_dir)
+        .map_err(BuildError::io("Failed to create the library directory"))?;
+    let mut fr = new_build(witnesscalc_path, config);
+    for source in FR_SOURCES {
+        fr.file(find_source(witnesscalc_path, source)?);
+    }
+    compile_library(
+        &mut fr,
+        "fr",
+        &witnesscalc_path.join("build_cc").join("fr"),
+        lib_dir,
+    )
+}
+
+fn new_build(witnesscalc_path: &Path, config: &CcConfig) -> cc::Build {
+    let mut build = cc::Build::new();
+    build
+        .cpp(true)
+        .std("c++17")
+        .target(config.target)
+        .host(config.host)
+        .opt_level(3)
+        .warnings(false)
+        .cargo_metadata(false);
+    for dir in SOURCE_DIRS.iter().chain(INCLUDE_DIRS) {
+        let dir = witnesscalc_path.join(dir);
+        if dir.is_dir() {
+            build.include(dir);
+        }
+    }
+    if let Some(gmp_include) = &config.gmp_include {
+        build.include(gmp_include);
+    }
+    for flag in config.cxx_flags {
+        build.flag(flag);
+    }
+    build
+}
+
+/// Compiles `build` into `lib<name>.a` in `lib_dir`, keeping its objects in `obj_dir`.
+fn compile_library(
+    build: &mut cc::Build,
+    name: &str,
+    obj_dir: &Path,
+    lib_dir: &Path,
+) -> Result<(), BuildError> {
+    fs::create_dir_all(obj_dir).map_err(BuildError::io("Failed to create the object directory"))?;
+    build
+        .out_dir(obj_dir)
+        .try_compile(name)
+        .map_err(|e| BuildError::Compile(format!("Failed to compile {}: {}", name, e)))?;
+    let lib = format!("lib{}.a", name);
+    fs::copy(obj_dir.join(&lib), lib_dir.join(&lib))
+        .map_err(BuildError::io(format!("Failed to copy {}", lib)))?;
+    Ok(())
+}
+
+fn find_source(witnesscalc_path: &Path, name: &str) -> Result<PathBuf, BuildError> {
+    SOURCE_DIRS
+        .iter()
+        .map(|dir| witnesscalc_path.join(dir).join(name))
+        .find(|path| path.exists())
+        .ok_or_else(|| {
+            BuildError::Config(format!(
+                "The cc backend needs {} in {} of the witnesscalc sources",
+                name,
+                SOURCE_DIRS.join(" or ")
+            ))
+        })
+}
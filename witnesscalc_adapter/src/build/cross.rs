@@ -0,0 +1,95 @@
+use std::{
+    ffi::{OsStr, OsString},
+    path::PathBuf,
+    process::Command,
+};
+
+use super::error::BuildError;
+
+/// The C/C++ toolchain of a cross-compiled Linux target, as cargo configures it for the `cc`
+/// crate: `CC_<target>`, `CXX_<target>`, `AR_<target>` and their `TARGET_` and plain
+/// variants, or the `<triple>-gcc` style tools by default.
+pub(super) struct Toolchain {
+    cc: PathBuf,
+    cxx: PathBuf,
+    ar: OsString,
+    ranlib: OsString,
+    /// Flags the target needs, e.g. `--target` for clang
+    cflags: OsString,
+    cxxflags: OsString,
+}
+
+impl Toolchain {
+    pub(super) fn new(target: &str, host: &str) -> Result<Self, BuildError> {
+        let mut build = cc::Build::new();
+        build
+            .target(target)
+            .host(host)
+            .opt_level(2)
+            .debug(false)
+            .warnings(false)
+            .cargo_metadata(false);
+        let toolchain_error =
+            |e: cc::Error| BuildError::Config(format!("No toolchain for {}: {}", target, e));
+        let cc = build.try_get_compiler().map_err(toolchain_error)?;
+        let cxx = build
+            .cpp(true)
+            .try_get_compiler()
+            .map_err(toolchain_error)?;
+        Ok(Self {
+            cc: cc.path().to_path_buf(),
+            cxx: cxx.path().to_path_buf(),
+            ar: build
+                .try_get_archiver()
+                .map_err(toolchain_error)?
+                .get_program()
+                .to_os_string(),
+            ranlib: build
+                .try_get_ranlib()
+                .map_err(toolchain_error)?
+                .get_program()
+                .to_os_string(),
+            cflags: cc.cflags_env(),
+            cxxflags: cxx.cflags_env(),
+        })
+    }
+
+    /// Sets the toolchain for a GMP `configure` run.
+    pub(super) fn configure_env(&self, configure: &mut Command) {
+        configure
+            .env("CC", &self.cc)
+            .env("CXX", &self.cxx)
+            .env("AR", &self.ar)
+            .env("RANLIB", &self.ranlib)
+            .env("CFLAGS", &self.cflags)
+            .env("CXXFLAGS", &self.cxxflags);
+    }
+
+    /// CMake toolchain settings cross-compiling to `system_processor` Linux.
+    pub(super) fn cmake_settings(&self, system_processor: &str) -> String {
+        let path = |path: &OsStr| path.to_string_lossy().replace('\\', "/");
+        format!(
+            "set(CMAKE_SYSTEM_NAME Linux)\n\
+             set(CMAKE_SYSTEM_PROCESSOR {})\n\
+             set(CMAKE_C_COMPILER \"{}\")\n\
+             set(CMAKE_CXX_COMPILER \"{}\")\n\
+             set(CMAKE_AR \"{}\" CACHE FILEPATH \"\")\n\
+             set(CMAKE_RANLIB \"{}\" CACHE FILEPATH \"\")\n\
+             set(CMAKE_C_FLAGS_INIT \"{}\")\n\
+             set(CMAKE_CXX_FLAGS_INIT \"{}\")\n\
+             set(CMAKE_FIND_ROOT_PATH_MODE_PROGRAM NEVER)\n",
+            system_processor,
+            path(self.cc.as_os_str()),
+            path(self.cxx.as_os_str()),
+            path(&self.ar),
+            path(&self.ranlib),
+            self.cflags.to_string_lossy(),
+            self.cxxflags.to_string_lossy(),
+        )
+    }
+
+    /// Sets the compilers for the tools `make` runs besides CMake.
+    pub(super) fn make_env(&self, make: &mut Command) {
+        make.env("CC", &self.cc).env("CXX", &self.cxx);
+    }
+}
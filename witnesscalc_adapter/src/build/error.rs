@@ -0,0 +1,160 @@
+use std::{
+    error, fmt,
+    io::{self, BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    process::{Command, Output, Stdio},
+    thread,
+};
+
+/// Number of trailing output lines kept in [`BuildError::Command`].
+const OUTPUT_TAIL_LINES: usize = 30;
+
+/// Why [`crate::Build::compile`] failed.
+#[derive(Debug)]
+pub enum BuildError {
+    /// The build configuration is invalid, e.g. the circuits directory does not exist.
+    Config(String),
+    /// A file system operation failed.
+    Io { context: String, source: io::Error },
+    /// An external command could not be started or exited unsuccessfully.
+    Command {
+        command: String,
+        cwd: PathBuf,
+        /// The exit status, or why the command could not be started
+        status: String,
+        /// The last lines of the command's stderr, or of its stdout when stderr is empty
+        output_tail: String,
+    },
+    /// Fetched sources or downloads do not match their pinned revision or checksum.
+    Integrity(String),
+    /// Compiling the C++ sources with the `cc` backend failed.
+    Compile(String),
+}
+
+impl BuildError {
+    pub(crate) fn io(context: impl Into<String>) -> impl FnOnce(io::Error) -> Self {
+        let context = context.into();
+        move |source| BuildError::Io { context, source }
+    }
+
+    fn command(command: &Command, status: String, output_tail: String) -> Self {
+        let program = command.get_program().to_string_lossy().into_owned();
+        let command_line = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .fold(program, |line, arg| line + " " + &arg);
+        BuildError::Command {
+            command: command_line,
+            cwd: command
+                .get_current_dir()
+                .map(Path::to_path_buf)
+                .or_else(|| std::env::current_dir().ok())
+                .unwrap_or_default(),
+            status,
+            output_tail,
+        }
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::Config(message)
+            | BuildError::Integrity(message)
+            | BuildError::Compile(message) => f.write_str(message),
+            BuildError::Io { context, source } => write!(f, "{}: {}", context, source),
+            BuildError::Command {
+                command,
+                cwd,
+                status,
+                output_tail,
+            } => {
+                write!(f, "`{}` failed ({}) in {}", command, status, cwd.display())?;
+                if !output_tail.is_empty() {
+                    write!(f, "\n{}", output_tail)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl error::Error for BuildError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            BuildError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// Runs `command` to completion, capturing its output. Only fails if it cannot be started.
+pub(crate) fn output(command: &mut Command) -> Result<Output, BuildError> {
+    command
+        .output()
+        .map_err(|e| BuildError::command(command, format!("failed to start: {}", e), String::new()))
+}
+
+/// Like [`output`], calling `on_line` with each line of stdout as it is printed.
+pub(crate) fn output_lines(
+    command: &mut Command,
+    mut on_line: impl FnMut(&str),
+) -> Result<Output, BuildError> {
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            BuildError::command(command, format!("failed to start: {}", e), String::new())
+        })?;
+    let mut stderr = child.stderr.take().unwrap();
+    let stderr_reader = thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = stderr.read_to_end(&mut buf);
+        buf
+    });
+    let mut stdout = Vec::new();
+    for line in BufReader::new(child.stdout.take().unwrap()).split(b'\n') {
+        let Ok(line) = line else {
+            break;
+        };
+        on_line(&String::from_utf8_lossy(&line));
+        stdout.extend_from_slice(&line);
+        stdout.push(b'\n');
+    }
+    let status = child.wait().map_err(|e| {
+        BuildError::command(command, format!("failed to wait: {}", e), String::new())
+    })?;
+    Ok(Output {
+        status,
+        stdout,
+        stderr: stderr_reader.join().unwrap_or_default(),
+    })
+}
+
+/// Runs `command` to completion and fails unless it exits successfully.
+pub(crate) fn run(command: &mut Command) -> Result<Output, BuildError> {
+    let output = output(command)?;
+    check(command, &output)?;
+    Ok(output)
+}
+
+/// Fails with the tail of the command's output unless it exited successfully.
+pub(crate) fn check(command: &Command, output: &Output) -> Result<(), BuildError> {
+    if output.status.success() {
+        return Ok(());
+    }
+    let stream = if output.stderr.iter().all(u8::is_ascii_whitespace) {
+        &output.stdout
+    } else {
+        &output.stderr
+    };
+    let text = String::from_utf8_lossy(stream);
+    let lines = text.lines().collect::<Vec<_>>();
+    let tail = lines[lines.len().saturating_sub(OUTPUT_TAIL_LINES)..].join("\n");
+    Err(BuildError::command(
+        command,
+        output.status.to_string(),
+        tail,
+    ))
+}
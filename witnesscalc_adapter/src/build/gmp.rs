@@ -0,0 +1,183 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use super::{
+    cross::Toolchain,
+    error::{self, BuildError},
+    source, Gmp,
+};
+
+/// A GMP build made outside the witnesscalc tree, by the system or `gmp-mpfr-sys`.
+pub(super) struct ExternalGmp {
+    /// Directory of `gmp.h`, unless it is in the compiler's default search path
+    pub(super) include_dir: Option<PathBuf>,
+    /// Directory of the GMP library
+    pub(super) lib_dir: PathBuf,
+    /// Directories to add to the linker search path, for GMP linked by this crate
+    pub(super) link_paths: Vec<PathBuf>,
+}
+
+impl ExternalGmp {
+    /// Locates the GMP build `gmp` selects, which must not be [`Gmp::Bundled`].
+    pub(super) fn probe(gmp: Gmp, cargo_metadata: bool) -> Result<Self, BuildError> {
+        match gmp {
+            Gmp::Bundled => unreachable!("the bundled GMP is built in the witnesscalc tree"),
+            Gmp::System => {
+                let mut config = pkg_config::Config::new();
+                config.cargo_metadata(false).env_metadata(cargo_metadata);
+                let library = config.probe("gmp").map_err(|e| {
+                    BuildError::Config(format!(
+                        "Failed to find the system GMP with pkg-config, install the GMP \
+                         development files (e.g. libgmp-dev): {}",
+                        e
+                    ))
+                })?;
+                // pkg-config leaves out the default search paths, which the package variables
+                // still name
+                let lib_dir = library
+                    .link_paths
+                    .first()
+                    .cloned()
+                    .or_else(|| variable("libdir"))
+                    .ok_or_else(|| {
+                        BuildError::Config("pkg-config does not name a GMP libdir".to_string())
+                    })?;
+                let include_dir = library
+                    .include_paths
+                    .first()
+                    .cloned()
+                    .or_else(|| variable("includedir"))
+                    .filter(|dir| dir.join("gmp.h").exists());
+                Ok(Self {
+                    include_dir,
+                    lib_dir,
+                    link_paths: library.link_paths,
+                })
+            }
+            Gmp::GmpMpfrSys => {
+                // Set by the gmp-mpfr-sys build script for crates depending on it directly
+                let dep_dir = |name: &str| {
+                    if cargo_metadata {
+                        println!("cargo:rerun-if-env-changed={}", name);
+                    }
+                    env::var_os(name).map(PathBuf::from).ok_or_else(|| {
+                        BuildError::Config(format!(
+                            "{} is not set, add gmp-mpfr-sys to the [dependencies] of the \
+                             crate calling the build, without its use-system-libs feature",
+                            name
+                        ))
+                    })
+                };
+                Ok(Self {
+                    include_dir: Some(dep_dir("DEP_GMP_INCLUDE_DIR")?),
+                    lib_dir: dep_dir("DEP_GMP_LIB_DIR")?,
+                    link_paths: Vec::new(),
+                })
+            }
+        }
+    }
+
+    /// Puts the GMP headers and static library where the witnesscalc CMake build expects
+    /// the GMP it builds itself, replacing any GMP already there.
+    pub(super) fn install(
+        &self,
+        witnesscalc_path: &Path,
+        gmp_lib_folder: &str,
+    ) -> Result<(), BuildError> {
+        let static_lib = self.lib_dir.join("libgmp.a");
+        if !static_lib.exists() {
+            return Err(BuildError::Config(format!(
+                "The make backend links GMP statically, but {} does not exist. Install the \
+                 static GMP library or use the cc backend",
+                static_lib.display()
+            )));
+        }
+        let package = witnesscalc_path
+            .join("depends")
+            .join("gmp")
+            .join(gmp_lib_folder);
+        if package.exists() {
+            fs::remove_dir_all(&package)
+                .map_err(BuildError::io("Failed to remove the previous GMP build"))?;
+        }
+        fs::create_dir_all(package.join("lib"))
+            .and_then(|_| fs::create_dir_all(package.join("include")))
+            .map_err(BuildError::io("Failed to create the GMP package directory"))?;
+        fs::copy(&static_lib, package.join("lib").join("libgmp.a"))
+            .map_err(BuildError::io("Failed to copy the GMP library"))?;
+        if let Some(include_dir) = &self.include_dir {
+            fs::copy(
+                include_dir.join("gmp.h"),
+                package.join("include").join("gmp.h"),
+            )
+            .map_err(BuildError::io("Failed to copy the GMP header"))?;
+        }
+        Ok(())
+    }
+}
+
+/// A path variable of the GMP pkg-config package.
+fn variable(name: &str) -> Option<PathBuf> {
+    pkg_config::get_variable("gmp", name)
+        .ok()
+        .filter(|value| !value.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Builds GMP into `depends/gmp/<gmp_lib_folder>` with its `configure` script, where
+/// `build_gmp.sh` does not build the GMP needed: for a cross-compiled Linux target, given as
+/// its GNU triple and toolchain, as the script only cross-compiles to mobile platforms, or
+/// `fat` with the code of every x86 CPU selected at runtime, where the script tunes it to
+/// the build machine. The GMP archive must already be in `depends/`.
+pub(super) fn build(
+    witnesscalc_path: &Path,
+    gmp_lib_folder: &str,
+    cross: Option<(&str, &Toolchain)>,
+    fat: bool,
+    configure_jobs: impl Fn(&mut Command),
+) -> Result<(), BuildError> {
+    let depends = witnesscalc_path.join("depends");
+    let src = depends.join(format!(
+        "gmp-{}",
+        cross.map_or("host", |(cross_host, _)| cross_host)
+    ));
+    if src.exists() {
+        fs::remove_dir_all(&src)
+            .map_err(BuildError::io("Failed to clean the GMP source directory"))?;
+    }
+    fs::create_dir_all(&src)
+        .map_err(BuildError::io("Failed to create the GMP source directory"))?;
+    error::run(
+        Command::new("tar")
+            .arg("-xf")
+            .arg(depends.join(source::GMP_ARCHIVE_NAME))
+            .arg("--strip-components=1")
+            .arg("-C")
+            .arg(&src),
+    )?;
+
+    let prefix = depends.join("gmp").join(gmp_lib_folder);
+    let mut configure = Command::new("sh");
+    configure
+        .current_dir(&src)
+        .arg("./configure")
+        .arg(format!("--prefix={}", prefix.display()))
+        .args(["--enable-static", "--disable-shared", "--with-pic"]);
+    if let Some((cross_host, toolchain)) = cross {
+        configure.arg(format!("--host={}", cross_host));
+        toolchain.configure_env(&mut configure);
+    }
+    if fat {
+        configure.arg("--enable-fat");
+    }
+    error::run(&mut configure)?;
+    let mut make = Command::new("make");
+    make.current_dir(&src);
+    configure_jobs(&mut make);
+    error::run(&mut make)?;
+    error::run(Command::new("make").current_dir(&src).arg("install"))?;
+    Ok(())
+}
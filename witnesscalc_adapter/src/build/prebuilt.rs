@@ -0,0 +1,340 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use serde_json::{json, Value};
+
+use super::{
+    error::{self, BuildError},
+    runtime,
+};
+use crate::circuit::sha256_hex;
+
+/// Name of the manifest describing a directory of prebuilt libraries.
+pub(super) const MANIFEST_NAME: &str = "witnesscalc-manifest.json";
+
+/// What a directory of prebuilt libraries was built from:
+///
+/// ```json
+/// {
+///   "target": "aarch64-apple-ios",
+///   "witnesscalc_revisions": { "v2.1.0": "<commit>", "v2.2.0": null },
+///   "cpu_dispatch": false,
+///   "circuits": {
+///     "multiplier2": { "runtime": "v2.1.0", "cpp_sha256": "<hash>", "dat_sha256": "<hash>" }
+///   }
+/// }
+/// ```
+pub(super) struct Manifest {
+    /// Where the manifest is read from, or the bundle it is written to
+    pub(super) path: PathBuf,
+    pub(super) target: String,
+    /// Commit each runtime was built at, `None` when it was not pinned
+    pub(super) witnesscalc_revisions: BTreeMap<String, Option<String>>,
+    /// Whether the baseline variants of the circuits were built as well
+    pub(super) cpu_dispatch: bool,
+    pub(super) circuits: BTreeMap<String, ManifestCircuit>,
+}
+
+/// A circuit of a [`Manifest`].
+pub(super) struct ManifestCircuit {
+    pub(super) runtime: &'static runtime::Runtime,
+    /// Hash of the circuit's generated `.cpp` file
+    pub(super) cpp_sha256: String,
+    /// Hash of the circuit's `.dat` file
+    pub(super) dat_sha256: String,
+}
+
+impl Manifest {
+    /// Reads the manifest of the prebuilt libraries in `dir`.
+    pub(super) fn read(dir: &Path) -> Result<Self, BuildError> {
+        let path = dir.join(MANIFEST_NAME);
+        let manifest = fs::read_to_string(&path).map_err(BuildError::io(format!(
+            "Failed to read the prebuilt manifest {}",
+            path.display()
+        )))?;
+        let invalid = |what: &str| {
+            BuildError::Config(format!(
+                "Invalid prebuilt manifest {}: {}",
+                path.display(),
+                what
+            ))
+        };
+        let manifest: Value =
+            serde_json::from_str(&manifest).map_err(|e| invalid(&e.to_string()))?;
+        let string = |value: &Value, key: &str| {
+            value[key]
+                .as_str()
+                .map(String::from)
+                .ok_or_else(|| invalid(&format!("{} must be a string", key)))
+        };
+
+        let target = string(&manifest, "target")?;
+        let witnesscalc_revisions = manifest["witnesscalc_revisions"]
+            .as_object()
+            .ok_or_else(|| invalid("witnesscalc_revisions must be an object"))?
+            .iter()
+            .map(|(version, commit)| match commit {
+                Value::Null => Ok((version.clone(), None)),
+                Value::String(commit) => Ok((version.clone(), Some(commit.clone()))),
+                _ => Err(invalid(&format!(
+                    "the revision of {} must be a string or null",
+                    version
+                ))),
+            })
+            .collect::<Result<_, _>>()?;
+        let cpu_dispatch = match &manifest["cpu_dispatch"] {
+            Value::Null => false,
+            Value::Bool(cpu_dispatch) => *cpu_dispatch,
+            _ => return Err(invalid("cpu_dispatch must be a boolean")),
+        };
+        let circuits = manifest["circuits"]
+            .as_object()
+            .ok_or_else(|| invalid("circuits must be an object"))?
+            .iter()
+            .map(|(name, circuit)| {
+                Ok((
+                    name.clone(),
+                    ManifestCircuit {
+                        runtime: runtime::find(&string(circuit, "runtime")?)?,
+                        cpp_sha256: string(circuit, "cpp_sha256")?,
+                        dat_sha256: string(circuit, "dat_sha256")?,
+                    },
+                ))
+            })
+            .collect::<Result<_, BuildError>>()?;
+        Ok(Self {
+            path,
+            target,
+            witnesscalc_revisions,
+            cpu_dispatch,
+            circuits,
+        })
+    }
+
+    fn to_json(&self) -> String {
+        let circuits = self
+            .circuits
+            .iter()
+            .map(|(name, circuit)| {
+                (
+                    name.clone(),
+                    json!({
+                        "runtime": circuit.runtime.version,
+                        "cpp_sha256": circuit.cpp_sha256,
+                        "dat_sha256": circuit.dat_sha256,
+                    }),
+                )
+            })
+            .collect::<serde_json::Map<_, _>>();
+        let manifest = json!({
+            "target": self.target,
+            "witnesscalc_revisions": self.witnesscalc_revisions,
+            "cpu_dispatch": self.cpu_dispatch,
+            "circuits": circuits,
+        });
+        serde_json::to_string_pretty(&manifest).unwrap() + "\n"
+    }
+
+    /// Checks that the libraries were built for `target` from the given circuit sources and
+    /// from the witnesscalc revisions `pinned_revision` pins, returning the manifest entry of
+    /// every circuit.
+    pub(super) fn verify<'a>(
+        &'a self,
+        target: &str,
+        circuits: &[(&str, &str, &[u8])],
+        pinned_revision: impl Fn(&str) -> Option<String>,
+    ) -> Result<Vec<&'a ManifestCircuit>, BuildError> {
+        let mismatch = |what: String| {
+            BuildError::Integrity(format!(
+                "The prebuilt manifest {} does not match this build: {}",
+                self.path.display(),
+                what
+            ))
+        };
+        if self.target != target {
+            return Err(mismatch(format!(
+                "they were built for {}, not {}",
+                self.target, target
+            )));
+        }
+        let mut entries = Vec::new();
+        for (name, cpp, dat) in circuits {
+            let entry = self
+                .circuits
+                .get(*name)
+                .ok_or_else(|| mismatch(format!("circuit {} was not built", name)))?;
+            if entry.cpp_sha256 != sha256_hex(cpp.as_bytes()) {
+                return Err(mismatch(format!("{}.cpp has changed", name)));
+            }
+            if entry.dat_sha256 != sha256_hex(dat) {
+                return Err(mismatch(format!("{}.dat has changed", name)));
+            }
+            let built = self
+                .witnesscalc_revisions
+                .get(entry.runtime.version)
+                .cloned()
+                .flatten();
+            let pinned = pinned_revision(entry.runtime.version);
+            if built != pinned {
+                return Err(mismatch(format!(
+                    "witnesscalc {} was built at {}, but is pinned to {}",
+                    entry.runtime.version,
+                    built.as_deref().unwrap_or("an unpinned revision"),
+                    pinned.as_deref().unwrap_or("no revision"),
+                )));
+            }
+            entries.push(entry);
+        }
+        Ok(entries)
+    }
+}
+
+/// The flags `tar` creates the archive `dest` with, or `None` when the bundle is a directory.
+pub(super) fn tar_flags(dest: &Path) -> Option<&'static str> {
+    let file_name = dest
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    if file_name.ends_with(".tar.gz") || file_name.ends_with(".tgz") {
+        Some("-czf")
+    } else if file_name.ends_with(".tar") {
+        Some("-cf")
+    } else {
+        None
+    }
+}
+
+/// Writes a bundle of `files`, given as their source and their path in the bundle, with
+/// `manifest` to `dest`: a directory, or a `.tar`, `.tar.gz` or `.tgz` archive assembled in
+/// `staging`. The bundle can be linked with [`crate::Build::prebuilt_dir`].
+pub(super) fn export(
+    dest: &Path,
+    staging: &Path,
+    files: &[(PathBuf, String)],
+    manifest: &Manifest,
+) -> Result<(), BuildError> {
+    let tar_flags = tar_flags(dest);
+    let dir = match tar_flags {
+        Some(_) => {
+            if staging.exists() {
+                fs::remove_dir_all(staging).map_err(BuildError::io(
+                    "Failed to clean the bundle staging directory",
+                ))?;
+            }
+            staging
+        }
+        None => dest,
+    };
+    for (source, path) in files {
+        let file = dir.join(path);
+        fs::create_dir_all(file.parent().unwrap())
+            .and_then(|_| fs::copy(source, &file))
+            .map_err(BuildError::io(format!(
+                "Failed to copy {} to the bundle",
+                source.display()
+            )))?;
+    }
+    fs::write(dir.join(MANIFEST_NAME), manifest.to_json())
+        .map_err(BuildError::io("Failed to write the bundle manifest"))?;
+
+    if let Some(tar_flags) = tar_flags {
+        if let Some(parent) = dest
+            .parent()
+            .filter(|parent| !parent.as_os_str().is_empty())
+        {
+            fs::create_dir_all(parent)
+                .map_err(BuildError::io("Failed to create the bundle directory"))?;
+        }
+        // Relative to the build script's directory rather than the staging directory
+        let dest = std::path::absolute(dest)
+            .map_err(BuildError::io("Failed to resolve the bundle path"))?;
+        error::run(
+            Command::new("tar")
+                .arg(tar_flags)
+                .arg(&dest)
+                .arg("-C")
+                .arg(staging)
+                .arg("."),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CPP: &str = "uint get_size_of_witness() {return 4;}\n";
+    const DAT: &[u8] = b"dat";
+
+    fn manifest(revision: Option<&str>) -> Manifest {
+        Manifest {
+            path: PathBuf::from(MANIFEST_NAME),
+            target: "aarch64-apple-ios".to_string(),
+            witnesscalc_revisions: BTreeMap::from([(
+                "v2.1.0".to_string(),
+                revision.map(String::from),
+            )]),
+            cpu_dispatch: false,
+            circuits: BTreeMap::from([(
+                "multiplier2".to_string(),
+                ManifestCircuit {
+                    runtime: runtime::find("v2.1.0").unwrap(),
+                    cpp_sha256: sha256_hex(CPP.as_bytes()),
+                    dat_sha256: sha256_hex(DAT),
+                },
+            )]),
+        }
+    }
+
+    #[test]
+    fn test_verify() {
+        let target = "aarch64-apple-ios";
+        let circuit = ("multiplier2", CPP, DAT);
+        let cases: [(_, _, &[_], Option<&str>, bool); 8] = [
+            (None, target, &[circuit], None, true),
+            (Some("abc"), target, &[circuit], Some("abc"), true),
+            // Only some of the circuits are linked
+            (None, target, &[], None, true),
+            (None, "x86_64-apple-ios", &[circuit], None, false),
+            (None, target, &[("sha256", CPP, DAT)], None, false),
+            (None, target, &[("multiplier2", "", DAT)], None, false),
+            (None, target, &[("multiplier2", CPP, b"")], None, false),
+            (Some("abc"), target, &[circuit], Some("def"), false),
+        ];
+        for (built, target, circuits, pinned, ok) in cases {
+            let manifest = manifest(built);
+            let verified = manifest.verify(target, circuits, |_| pinned.map(String::from));
+            match verified {
+                Ok(entries) => {
+                    assert!(ok, "{} {:?}", target, pinned);
+                    assert_eq!(entries.len(), circuits.len());
+                }
+                Err(e) => {
+                    assert!(!ok, "{} {:?}: {}", target, pinned, e);
+                    assert!(matches!(e, BuildError::Integrity(_)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let dir = std::env::temp_dir().join(format!("witnesscalc-manifest-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join(MANIFEST_NAME), manifest(Some("abc")).to_json()).unwrap();
+        let read = Manifest::read(&dir).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(read.target, "aarch64-apple-ios");
+        assert_eq!(read.witnesscalc_revisions["v2.1.0"].as_deref(), Some("abc"));
+        assert!(read
+            .verify("aarch64-apple-ios", &[("multiplier2", CPP, DAT)], |_| Some(
+                "abc".to_string()
+            ))
+            .is_ok());
+    }
+}
@@ -0,0 +1,197 @@
+use std::{
+    ffi::{OsStr, OsString},
+    io,
+    process::Command,
+};
+
+use super::error::BuildError;
+
+/// A program the build runs.
+pub(super) struct Tool {
+    /// What the tool is, e.g. `cmake` or `C++ compiler`
+    name: String,
+    program: OsString,
+    /// What the build needs it for
+    purpose: &'static str,
+    min_version: Option<(u32, u32)>,
+    install: Install,
+}
+
+/// How to install a [`Tool`].
+enum Install {
+    /// Packages providing the tool with apt on Linux, and brew on macOS
+    Package {
+        apt: &'static str,
+        brew: &'static str,
+    },
+    Instruction(String),
+}
+
+impl Tool {
+    /// A tool found in `PATH`, installed by the packages of the same name.
+    pub(super) fn new(program: &'static str, purpose: &'static str) -> Self {
+        Self {
+            name: program.to_string(),
+            program: program.into(),
+            purpose,
+            min_version: None,
+            install: Install::Package {
+                apt: program,
+                brew: program,
+            },
+        }
+    }
+
+    /// The C (or C++ if `cpp`) compiler the `cc` crate picks for `target`.
+    pub(super) fn compiler(cpp: bool, target: &str, host: &str, purpose: &'static str) -> Self {
+        let mut build = cc::Build::new();
+        build
+            .cpp(cpp)
+            .target(target)
+            .host(host)
+            .opt_level(2)
+            .debug(false)
+            .warnings(false)
+            .cargo_metadata(false);
+        let default = if cpp { "c++" } else { "cc" };
+        let program = build
+            .try_get_compiler()
+            .map(|compiler| compiler.path().as_os_str().to_os_string())
+            .unwrap_or_else(|_| default.into());
+        Self {
+            name: format!(
+                "{} compiler ({})",
+                if cpp { "C++" } else { "C" },
+                program.to_string_lossy()
+            ),
+            program,
+            purpose,
+            min_version: None,
+            install: if target != host {
+                Install::Instruction(format!(
+                    "install the cross toolchain of {} and configure it as described for Linux \
+                     targets in the README",
+                    target
+                ))
+            } else if cfg!(target_os = "macos") {
+                Install::Instruction("`xcode-select --install`".to_string())
+            } else {
+                Install::Package {
+                    apt: "build-essential",
+                    brew: "",
+                }
+            },
+        }
+    }
+
+    /// The `nm` or `objcopy` `program` resolved for `target`.
+    pub(super) fn symbol_tool(
+        program: &OsStr,
+        target: &str,
+        host: &str,
+        purpose: &'static str,
+    ) -> Self {
+        Self {
+            name: program.to_string_lossy().into_owned(),
+            program: program.to_os_string(),
+            purpose,
+            min_version: None,
+            install: if target != host && !target.contains("-apple-") {
+                Install::Instruction(format!(
+                    "install the cross toolchain of {}, or set NM and OBJCOPY to its tools",
+                    target
+                ))
+            } else {
+                // Apple targets need the LLVM tools, which provide an objcopy
+                Install::Package {
+                    apt: "binutils",
+                    brew: "llvm",
+                }
+            },
+        }
+    }
+
+    pub(super) fn min_version(mut self, major: u32, minor: u32) -> Self {
+        self.min_version = Some((major, minor));
+        self
+    }
+
+    /// Why the tool can't be used, if it can't.
+    fn problem(&self) -> Option<String> {
+        let output = match Command::new(&self.program).arg("--version").output() {
+            Ok(output) => output,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Some("not found".to_string()),
+            Err(e) => return Some(format!("failed to run: {}", e)),
+        };
+        let (major, minor) = self.min_version?;
+        // e.g. "cmake version 3.28.3"
+        let found = String::from_utf8_lossy(&output.stdout)
+            .split_whitespace()
+            .find_map(|word| {
+                let mut parts = word.split('.').map(|part| part.parse::<u32>().ok());
+                Some((parts.next()??, parts.next()??))
+            });
+        match found {
+            Some(found) if found >= (major, minor) => None,
+            Some((found_major, found_minor)) => Some(format!(
+                "version {}.{} found, {}.{} or later is required",
+                found_major, found_minor, major, minor
+            )),
+            None => Some(format!(
+                "unknown version, {}.{} or later is required",
+                major, minor
+            )),
+        }
+    }
+}
+
+/// Checks that every tool of `tools` can be run, failing with a single error listing the
+/// missing ones and how to install them otherwise.
+pub(super) fn check(tools: &[Tool]) -> Result<(), BuildError> {
+    let problems = tools
+        .iter()
+        .filter_map(|tool| tool.problem().map(|problem| (tool, problem)))
+        .collect::<Vec<_>>();
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    let mut message = "Missing build dependencies:".to_string();
+    for (tool, problem) in &problems {
+        message.push_str(&format!(
+            "\n  - {}, for {}: {}",
+            tool.name, tool.purpose, problem
+        ));
+    }
+    let mut packages = Vec::new();
+    let mut instructions = Vec::new();
+    for (tool, _) in &problems {
+        match &tool.install {
+            Install::Package { apt, brew } => {
+                let package = if cfg!(target_os = "macos") { brew } else { apt };
+                if !packages.contains(package) {
+                    packages.push(*package);
+                }
+            }
+            Install::Instruction(instruction) => {
+                if !instructions.contains(instruction) {
+                    instructions.push(instruction.clone());
+                }
+            }
+        }
+    }
+    if !packages.is_empty() {
+        let manager = if cfg!(target_os = "macos") {
+            "brew install"
+        } else {
+            "sudo apt install"
+        };
+        instructions.insert(0, format!("`{} {}`", manager, packages.join(" ")));
+    }
+    let install = instructions.join(" and ");
+    message.push_str(&format!(
+        "\nInstall them with {} (see the Requirements section of the witnesscalc-adapter README)",
+        install
+    ));
+    Err(BuildError::Config(message))
+}
@@ -0,0 +1,244 @@
+use std::path::{Path, PathBuf};
+
+use super::error::BuildError;
+
+/// A witnesscalc runtime version circuits can be built against.
+#[derive(Debug)]
+pub(super) struct Runtime {
+    /// Tag of the runtime in the witnesscalc repository
+    pub(super) version: &'static str,
+    /// The `uint get_*()` functions circom defines in the `.cpp` files it generates for this
+    /// runtime
+    getters: &'static [&'static str],
+}
+
+/// Supported runtimes, oldest first. The sources are fetched at the first one.
+pub(super) static RUNTIMES: &[Runtime] = &[
+    // circom 2.1.x
+    Runtime {
+        version: "v2.1.0",
+        getters: &[
+            "get_main_input_signal_start",
+            "get_main_input_signal_no",
+            "get_total_signal_no",
+            "get_number_of_components",
+            "get_size_of_input_hashmap",
+            "get_size_of_witness",
+            "get_size_of_constants",
+            "get_size_of_io_map",
+        ],
+    },
+    // circom 2.2.x, which added buses
+    Runtime {
+        version: "v2.2.0",
+        getters: &[
+            "get_main_input_signal_start",
+            "get_main_input_signal_no",
+            "get_total_signal_no",
+            "get_number_of_components",
+            "get_size_of_input_hashmap",
+            "get_size_of_witness",
+            "get_size_of_constants",
+            "get_size_of_io_map",
+            "get_size_of_bus_field_map",
+        ],
+    },
+];
+
+/// Looks up a runtime by its version, e.g. `"v2.2.0"`.
+pub(super) fn find(version: &str) -> Result<&'static Runtime, BuildError> {
+    RUNTIMES
+        .iter()
+        .find(|runtime| runtime.version == version)
+        .ok_or_else(|| {
+            BuildError::Config(format!(
+                "Unsupported witnesscalc runtime version {}, expected one of: {}",
+                version,
+                versions()
+            ))
+        })
+}
+
+/// Detects the runtime a circuit's generated `.cpp` file was generated for: the oldest one
+/// providing every `uint get_*()` function the circuit defines.
+///
+/// A circuit defining functions no runtime knows was generated by a newer circom, and fails
+/// instead of being compiled against a runtime that misreads its `.dat` file.
+pub(super) fn detect(
+    circuit_name: &str,
+    circuit_cpp: &str,
+) -> Result<&'static Runtime, BuildError> {
+    let getters = circuit_cpp
+        .lines()
+        .filter_map(|line| line.trim_start().strip_prefix("uint "))
+        .filter_map(|line| line.split_once('('))
+        .map(|(name, _)| name.trim())
+        .filter(|name| name.starts_with("get_"))
+        .collect::<Vec<_>>();
+    if !getters.contains(&"get_size_of_witness") {
+        return Err(BuildError::Config(format!(
+            "{}.cpp does not look like a circom generated C++ witness generator",
+            circuit_name
+        )));
+    }
+    RUNTIMES
+        .iter()
+        .find(|runtime| {
+            getters
+                .iter()
+                .all(|getter| runtime.getters.contains(getter))
+        })
+        .ok_or_else(|| {
+            let unknown = getters
+                .iter()
+                .filter(|getter| {
+                    !RUNTIMES
+                        .iter()
+                        .any(|runtime| runtime.getters.contains(getter))
+                })
+                .copied()
+                .collect::<Vec<_>>();
+            BuildError::Config(format!(
+                "{}.cpp was generated by an unsupported circom version (unknown {}), set its \
+                 runtime with Build::circuit_version to one of: {}",
+                circuit_name,
+                unknown.join(", "),
+                versions()
+            ))
+        })
+}
+
+/// The runtime whose libraries keep their symbol names among those of `circuit_runtimes`: the
+/// oldest one, or the base one without circuits. The others are given private names, to be
+/// linked next to it.
+pub(super) fn primary(circuit_runtimes: &[&Runtime]) -> &'static Runtime {
+    RUNTIMES
+        .iter()
+        .find(|runtime| {
+            circuit_runtimes
+                .iter()
+                .any(|circuit_runtime| std::ptr::eq(*circuit_runtime, *runtime))
+        })
+        .unwrap_or(&RUNTIMES[0])
+}
+
+fn versions() -> String {
+    RUNTIMES
+        .iter()
+        .map(|runtime| runtime.version)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Runtime {
+    /// Whether this is the runtime the sources are fetched at.
+    pub(super) fn is_base(&self) -> bool {
+        std::ptr::eq(self, &RUNTIMES[0])
+    }
+
+    /// Source tree the runtime is built in. Each runtime has its own, so building one never
+    /// changes the sources of another.
+    pub(super) fn tree(&self, out_dir: &Path) -> PathBuf {
+        if self.is_base() {
+            out_dir.join("witnesscalc")
+        } else {
+            out_dir.join(format!("witnesscalc-{}", self.version))
+        }
+    }
+
+    /// Source tree the baseline variant of the runtime is built in, for CPU dispatch.
+    pub(super) fn baseline_tree(&self, out_dir: &Path) -> PathBuf {
+        let mut tree = self.tree(out_dir).into_os_string();
+        tree.push("-baseline");
+        PathBuf::from(tree)
+    }
+
+    /// Name of the fr library circuits built against this runtime link: `fr` for the
+    /// `primary` runtime, `fr_<ident>` with private symbols for the others.
+    pub(super) fn fr_lib(&self, primary: &Runtime) -> String {
+        if std::ptr::eq(self, primary) {
+            "fr".to_string()
+        } else {
+            format!("fr_{}", self.ident())
+        }
+    }
+
+    /// Name of the fr library of the baseline variant, for CPU dispatch.
+    pub(super) fn baseline_fr_lib(&self, primary: &Runtime) -> String {
+        format!("{}_baseline", self.fr_lib(primary))
+    }
+
+    /// `v2.2.0` -> `v2_2_0`, for library and symbol names.
+    pub(super) fn ident(&self) -> String {
+        self.version.replace('.', "_")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A generated `.cpp` file defining `getters`.
+    fn circuit_cpp(getters: &[&str]) -> String {
+        let mut cpp = "#include \"circom.hpp\"\n".to_string();
+        for getter in getters {
+            cpp.push_str(&format!("uint {} () {{return 0;}}\n", getter));
+        }
+        cpp
+    }
+
+    #[test]
+    fn test_detect() {
+        let cases = [
+            (RUNTIMES[0].getters, "v2.1.0"),
+            (RUNTIMES[1].getters, "v2.2.0"),
+            (&["get_size_of_witness"][..], "v2.1.0"),
+            (
+                &["get_size_of_witness", "get_size_of_bus_field_map"][..],
+                "v2.2.0",
+            ),
+        ];
+        for (getters, version) in cases {
+            let runtime = detect("circuit", &circuit_cpp(getters)).unwrap();
+            assert_eq!(runtime.version, version, "{:?}", getters);
+        }
+    }
+
+    #[test]
+    fn test_primary() {
+        let (v2_1, v2_2) = (&RUNTIMES[0], &RUNTIMES[1]);
+        let cases: [(&[&Runtime], &Runtime); 4] = [
+            (&[], v2_1),
+            (&[v2_1], v2_1),
+            (&[v2_2, v2_2], v2_2),
+            (&[v2_2, v2_1], v2_1),
+        ];
+        for (circuit_runtimes, expected) in cases {
+            let primary = primary(circuit_runtimes);
+            assert_eq!(primary.version, expected.version, "{:?}", circuit_runtimes);
+            assert_eq!(expected.fr_lib(primary), "fr");
+        }
+        assert_eq!(v2_2.fr_lib(v2_1), "fr_v2_2_0");
+        assert_eq!(v2_2.baseline_fr_lib(v2_2), "fr_baseline");
+    }
+
+    #[test]
+    fn test_detect_unsupported() {
+        let cases = [
+            // Not a circom witness generator
+            &["get_main_input_signal_start"][..],
+            // Generated by a newer circom
+            &["get_size_of_witness", "get_size_of_unknown"][..],
+        ];
+        for getters in cases {
+            assert!(
+                matches!(
+                    detect("circuit", &circuit_cpp(getters)),
+                    Err(BuildError::Config(_))
+                ),
+                "{:?}",
+                getters
+            );
+        }
+    }
+}
@@ -0,0 +1,412 @@
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use super::{
+    error::{self, BuildError},
+    Warnings,
+};
+use crate::circuit::sha256_hex;
+
+const WITNESSCALC_BUILD_SCRIPT: &str = include_str!("../../clone_witnesscalc.sh");
+
+/// Name `build_gmp.sh` expects the GMP archive to have in `depends/`, skipping the download.
+pub(super) const GMP_ARCHIVE_NAME: &str = "gmp-6.2.1.tar.xz";
+const GMP_ARCHIVE_URL: &str = "https://ftp.gnu.org/gnu/gmp/gmp-6.2.1.tar.xz";
+const GMP_ARCHIVE_SHA256: &str = "fd4829912cddd12f84181c3451cc752be224643e87fac497b69edddadc49b4f2";
+
+/// Commits each supported witnesscalc runtime version is pinned to.
+///
+/// The fetched sources are checked out at, and verified against, these commits rather than
+/// the default branch or a tag that may move. A version without a commit here is built from
+/// the default branch (`v2.1.0`) or its tag (`v2.2.0`), unless one is provided with
+/// `Build::witnesscalc_revision` or `WITNESSCALC_REV_<VERSION>`, and the build warns that it
+/// is unpinned.
+///
+/// TODO: pin both runtimes to the commits of the zkmopro fork they are released from.
+pub(super) const PINNED_REVISIONS: &[(&str, Option<&str>)] = &[("v2.1.0", None), ("v2.2.0", None)];
+
+pub(super) fn pinned_revision(version: &str) -> Option<&'static str> {
+    PINNED_REVISIONS
+        .iter()
+        .find(|(pinned_version, _)| *pinned_version == version)
+        .and_then(|(_, commit)| *commit)
+}
+
+/// Where the witnesscalc sources come from.
+#[derive(Clone, Debug)]
+pub(super) enum WitnesscalcSource {
+    /// Clone the given repository, or the zkmopro fork by default.
+    Git(Option<String>),
+    /// Copy a local checkout, or extract a `.tar`, `.tar.gz` or `.tar.xz` archive of one.
+    Local(PathBuf),
+}
+
+impl WitnesscalcSource {
+    /// Identifies where the sources come from, and for local ones when their files last changed.
+    pub(super) fn id(&self) -> String {
+        match self {
+            WitnesscalcSource::Git(repo) => format!("git {}", repo.as_deref().unwrap_or_default()),
+            WitnesscalcSource::Local(path) => {
+                let mut modified = Vec::new();
+                collect_modified(path, Path::new(""), &mut modified);
+                modified.sort();
+                format!(
+                    "local {} {}",
+                    path.display(),
+                    sha256_hex(modified.join("\n").as_bytes())
+                )
+            }
+        }
+    }
+
+    /// Populates `witnesscalc_path` with the witnesscalc sources.
+    pub(super) fn fetch(&self, witnesscalc_path: &Path) -> Result<(), BuildError> {
+        match self {
+            WitnesscalcSource::Git(repo) => {
+                let out_dir = witnesscalc_path.parent().unwrap();
+                let witnesscalc_script_path = out_dir.join("clone_witnesscalc.sh");
+                fs::write(&witnesscalc_script_path, WITNESSCALC_BUILD_SCRIPT)
+                    .map_err(BuildError::io("Failed to write build script"))?;
+                let mut clone = Command::new("sh");
+                clone
+                    .arg(&witnesscalc_script_path)
+                    .env("BUILD_DIR", witnesscalc_path);
+                if let Some(repo) = repo {
+                    clone.env("WITNESSCALC_REPO", repo);
+                }
+                error::run(&mut clone)?;
+                Ok(())
+            }
+            WitnesscalcSource::Local(path) => copy_local_source(path, witnesscalc_path),
+        }
+    }
+}
+
+/// Copies a local witnesscalc checkout or archive to `dest`.
+pub(super) fn copy_local_source(path: &Path, dest: &Path) -> Result<(), BuildError> {
+    if !path.exists() {
+        return Err(BuildError::Config(format!(
+            "witnesscalc source {} does not exist",
+            path.display()
+        )));
+    }
+    if path.is_dir() {
+        // A checkout built in place also holds build output referring to it
+        return copy_sources(path, dest).map_err(BuildError::io(format!(
+            "Failed to copy witnesscalc source {}",
+            path.display()
+        )));
+    }
+
+    let extract_dir = dest.with_extension("extract");
+    if extract_dir.exists() {
+        fs::remove_dir_all(&extract_dir)
+            .map_err(BuildError::io("Failed to clean the extraction directory"))?;
+    }
+    fs::create_dir_all(&extract_dir)
+        .map_err(BuildError::io("Failed to create the extraction directory"))?;
+    error::run(
+        Command::new("tar")
+            .arg("-xf")
+            .arg(path)
+            .arg("-C")
+            .arg(&extract_dir),
+    )?;
+    // Archives of a repository usually contain a single top-level directory
+    let entries = fs::read_dir(&extract_dir)
+        .and_then(|entries| {
+            entries
+                .map(|entry| entry.map(|entry| entry.path()))
+                .collect::<io::Result<Vec<_>>>()
+        })
+        .map_err(BuildError::io("Failed to read the extraction directory"))?;
+    let root = match entries.as_slice() {
+        [root] if root.is_dir() => root.clone(),
+        _ => extract_dir.clone(),
+    };
+    fs::rename(&root, dest).map_err(BuildError::io(
+        "Failed to move the extracted witnesscalc source",
+    ))?;
+    let _ = fs::remove_dir_all(&extract_dir);
+    Ok(())
+}
+
+/// Places the GMP archive where `build_gmp.sh` looks for it before downloading, so only an
+/// archive matching the pinned checksum is ever built. Uses `archive` when given, and
+/// downloads it otherwise.
+pub(super) fn install_gmp_archive(
+    archive: Option<&Path>,
+    witnesscalc_path: &Path,
+) -> Result<(), BuildError> {
+    let dest = witnesscalc_path.join("depends").join(GMP_ARCHIVE_NAME);
+    if !dest.exists() {
+        match archive {
+            Some(archive) => {
+                fs::copy(archive, &dest).map_err(BuildError::io(format!(
+                    "Failed to copy GMP archive {}",
+                    archive.display()
+                )))?;
+            }
+            None => {
+                let download = error::run(
+                    Command::new("curl")
+                        .args([
+                            "--fail",
+                            "--silent",
+                            "--show-error",
+                            "--location",
+                            "--output",
+                        ])
+                        .arg(&dest)
+                        .arg(GMP_ARCHIVE_URL),
+                );
+                if download.is_err() {
+                    let _ = fs::remove_file(&dest);
+                }
+                download?;
+            }
+        }
+    }
+
+    let archive = fs::read(&dest).map_err(BuildError::io("Failed to read GMP archive"))?;
+    let sha256 = sha256_hex(&archive);
+    if sha256 != GMP_ARCHIVE_SHA256 {
+        let _ = fs::remove_file(&dest);
+        return Err(BuildError::Integrity(format!(
+            "GMP archive checksum mismatch: expected sha256 {}, got {}",
+            GMP_ARCHIVE_SHA256, sha256
+        )));
+    }
+    Ok(())
+}
+
+/// Checks out `commit` in a git checkout and verifies that the tree is at that commit.
+///
+/// When the checkout has `tag`, it must also resolve to `commit`, so a moved tag fails the
+/// build instead of silently changing the C++ sources.
+pub(super) fn pin_revision(
+    witnesscalc_path: &Path,
+    version: &str,
+    commit: &str,
+    warnings: &mut Warnings,
+) -> Result<(), BuildError> {
+    if !witnesscalc_path.join(".git").exists() {
+        warnings.warn(format!(
+            "witnesscalc {} source is not a git checkout, its revision cannot be verified against {}",
+            version, commit
+        ));
+        return Ok(());
+    }
+    if let Some(tag_commit) = git_rev_parse(witnesscalc_path, &format!("{}^{{commit}}", version)) {
+        if tag_commit != commit {
+            return Err(BuildError::Integrity(format!(
+                "witnesscalc tag {} resolves to commit {} but is pinned to {}",
+                version, tag_commit, commit
+            )));
+        }
+    }
+    let mut checkout = Command::new("git");
+    checkout
+        .args(["checkout", "--quiet", commit])
+        .current_dir(witnesscalc_path);
+    if error::run(&mut checkout).is_err() {
+        return Err(BuildError::Integrity(format!(
+            "witnesscalc {} is pinned to commit {}, which is not in the fetched repository",
+            version, commit
+        )));
+    }
+    let head = git_rev_parse(witnesscalc_path, "HEAD");
+    if head.as_deref() != Some(commit) {
+        return Err(BuildError::Integrity(format!(
+            "witnesscalc {} checkout is at commit {} instead of the pinned {}",
+            version,
+            head.unwrap_or_default(),
+            commit
+        )));
+    }
+    Ok(())
+}
+
+fn git_rev_parse(repo: &Path, rev: &str) -> Option<String> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--verify", "--quiet", rev])
+        .current_dir(repo)
+        .output()
+        .ok()?;
+    output
+        .status
+        .success()
+        .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Creates the source tree of another runtime version at `dest`, next to the base tree at
+/// `base_path` so building one never changes the sources of the other.
+///
+/// Git checkouts are copied and switched to the tag locally, without network access. Other
+/// sources need a local tree for that version. The GMP build of the base tree is reused.
+pub(super) fn create_runtime_tree(
+    base_path: &Path,
+    dest: &Path,
+    tag: &str,
+    local: Option<&Path>,
+    commit: Option<&str>,
+    gmp_lib_folder: &str,
+    warnings: &mut Warnings,
+) -> Result<(), BuildError> {
+    create_tree(dest, |staging_path| {
+        populate_runtime_tree(
+            base_path,
+            staging_path,
+            tag,
+            local,
+            commit,
+            gmp_lib_folder,
+            warnings,
+        )
+    })
+}
+
+fn populate_runtime_tree(
+    base_path: &Path,
+    staging_path: &Path,
+    tag: &str,
+    local: Option<&Path>,
+    commit: Option<&str>,
+    gmp_lib_folder: &str,
+    warnings: &mut Warnings,
+) -> Result<(), BuildError> {
+    match local {
+        Some(local) => {
+            copy_local_source(local, staging_path)?;
+            if let Some(commit) = commit {
+                pin_revision(staging_path, tag, commit, warnings)?;
+            }
+        }
+        None => {
+            if !base_path.join(".git").exists() {
+                return Err(BuildError::Config(format!(
+                    "The witnesscalc source is not a git checkout, set WITNESSCALC_SRC_{} to a {} source tree",
+                    super::version_env_suffix(tag),
+                    tag
+                )));
+            }
+            copy_sources(base_path, staging_path)
+                .map_err(BuildError::io("Failed to copy witnesscalc source"))?;
+            match commit {
+                Some(commit) => pin_revision(staging_path, tag, commit, warnings)?,
+                None => {
+                    error::run(
+                        Command::new("git")
+                            .args(["checkout", "--quiet", tag])
+                            .current_dir(staging_path),
+                    )?;
+                }
+            }
+        }
+    }
+
+    let gmp_path = Path::new("depends").join("gmp").join(gmp_lib_folder);
+    if base_path.join(&gmp_path).exists() && !staging_path.join(&gmp_path).exists() {
+        copy_dir(&base_path.join(&gmp_path), &staging_path.join(&gmp_path))
+            .map_err(BuildError::io("Failed to copy the GMP build"))?;
+    }
+    Ok(())
+}
+
+/// Creates `dest` as a copy of the runtime tree `base_path` with its GMP build, to build
+/// another variant of the same circuits in.
+pub(super) fn create_variant_tree(base_path: &Path, dest: &Path) -> Result<(), BuildError> {
+    create_tree(dest, |staging_path| {
+        copy_sources(base_path, staging_path)
+            .map_err(BuildError::io("Failed to copy the witnesscalc tree"))
+    })
+}
+
+/// Creates the tree `dest` with `populate`, which fills `<dest>.partial` that is then moved
+/// in place. An interrupted or failed build thus never leaves a partial tree behind to be
+/// reused.
+pub(super) fn create_tree(
+    dest: &Path,
+    populate: impl FnOnce(&Path) -> Result<(), BuildError>,
+) -> Result<(), BuildError> {
+    let mut staging_name = dest.file_name().unwrap().to_os_string();
+    staging_name.push(".partial");
+    let staging_path = dest.with_file_name(staging_name);
+    if staging_path.exists() {
+        fs::remove_dir_all(&staging_path).map_err(BuildError::io(format!(
+            "Failed to clean the staging directory {}",
+            staging_path.display()
+        )))?;
+    }
+    populate(&staging_path)?;
+    fs::rename(&staging_path, dest).map_err(BuildError::io(format!(
+        "Failed to move {} in place",
+        dest.display()
+    )))
+}
+
+/// Collects the modification time of every file under `path`, but the git metadata and the
+/// build output [`copy_sources`] skips, with its path relative to the source root.
+fn collect_modified(path: &Path, relative: &Path, modified: &mut Vec<String>) {
+    let Ok(metadata) = fs::metadata(path) else {
+        return;
+    };
+    if !metadata.is_dir() {
+        modified.push(format!(
+            "{} {:?}",
+            relative.display(),
+            metadata.modified().ok()
+        ));
+        return;
+    }
+    let Ok(entries) = fs::read_dir(path) else {
+        return;
+    };
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let top_level_output = relative.as_os_str().is_empty()
+            && (name == "package" || name.to_string_lossy().starts_with("build_"));
+        if name == ".git" || top_level_output {
+            continue;
+        }
+        collect_modified(&entry.path(), &relative.join(&name), modified);
+    }
+}
+
+/// Copies a witnesscalc tree without its build output, `package/` and the `build_*/` CMake
+/// directories, which refer to the tree they were built in.
+pub(super) fn copy_sources(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        let dest = dest.join(&name);
+        if entry.file_type()?.is_dir() {
+            let name = name.to_string_lossy();
+            if name == "package" || name.starts_with("build_") {
+                continue;
+            }
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
+
+fn copy_dir(src: &Path, dest: &Path) -> io::Result<()> {
+    fs::create_dir_all(dest)?;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let dest = dest.join(entry.file_name());
+        if entry.file_type()?.is_dir() {
+            copy_dir(&entry.path(), &dest)?;
+        } else {
+            fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
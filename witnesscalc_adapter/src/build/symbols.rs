@@ -0,0 +1,181 @@
+use std::{
+    collections::BTreeSet,
+    env,
+    ffi::OsString,
+    fs,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use super::error::{self, BuildError};
+
+/// The `nm` and `objcopy` reading and rewriting the libraries of a target.
+pub(super) struct SymbolTools {
+    pub(super) nm: OsString,
+    pub(super) objcopy: OsString,
+}
+
+impl SymbolTools {
+    /// Finds the tools of `target`'s toolchain, the first found of:
+    ///
+    /// - `NM_<target>`/`OBJCOPY_<target>`, `TARGET_NM`/`TARGET_OBJCOPY` or `NM`/`OBJCOPY`, like
+    ///   the `cc` crate reads its tools
+    /// - for Apple targets, which have no `objcopy`, the LLVM tools of Xcode or Homebrew
+    /// - the tools next to the archiver the `cc` crate picks for `target`, e.g. the NDK's
+    ///   `llvm-objcopy` next to its `llvm-ar`, or `aarch64-linux-gnu-objcopy` for
+    ///   `aarch64-linux-gnu-ar`
+    pub(super) fn resolve(target: &str, host: &str) -> Self {
+        let mut nm = env_tool("NM", target);
+        let mut objcopy = env_tool("OBJCOPY", target);
+        if target.contains("-apple-") {
+            let llvm_dir = xcrun_find("llvm-objcopy")
+                .or_else(|| {
+                    ["/opt/homebrew/opt/llvm/bin", "/usr/local/opt/llvm/bin"]
+                        .iter()
+                        .map(|dir| Path::new(dir).join("llvm-objcopy"))
+                        .find(|path| path.exists())
+                })
+                .map(|path| path.parent().unwrap().to_path_buf());
+            objcopy = objcopy.or_else(|| match &llvm_dir {
+                Some(dir) => Some(dir.join("llvm-objcopy").into()),
+                None => find_in_path("llvm-objcopy").map(Into::into),
+            });
+            nm = nm.or_else(|| {
+                llvm_dir
+                    .map(|dir| dir.join("llvm-nm"))
+                    .filter(|path| path.exists())
+                    .or_else(|| xcrun_find("nm"))
+                    .map(Into::into)
+            });
+        }
+        if nm.is_none() || objcopy.is_none() {
+            let archiver = cc::Build::new()
+                .target(target)
+                .host(host)
+                .opt_level(2)
+                .cargo_metadata(false)
+                .try_get_archiver()
+                .map(|archiver| PathBuf::from(archiver.get_program()))
+                .ok();
+            let sibling = |tool: &str| archiver.as_deref().and_then(|ar| sibling_tool(ar, tool));
+            nm = nm.or_else(|| sibling("nm"));
+            objcopy = objcopy.or_else(|| sibling("objcopy"));
+        }
+        let default = if target.contains("-apple-") {
+            "llvm-objcopy"
+        } else {
+            "objcopy"
+        };
+        Self {
+            nm: nm.unwrap_or_else(|| "nm".into()),
+            objcopy: objcopy.unwrap_or_else(|| default.into()),
+        }
+    }
+}
+
+fn env_tool(tool: &str, target: &str) -> Option<OsString> {
+    [
+        format!("{}_{}", tool, target),
+        format!("{}_{}", tool, target.replace('-', "_")),
+        format!("TARGET_{}", tool),
+        tool.to_string(),
+    ]
+    .iter()
+    .find_map(|var| env::var_os(var).filter(|value| !value.is_empty()))
+}
+
+fn xcrun_find(tool: &str) -> Option<PathBuf> {
+    let output = Command::new("xcrun").args(["--find", tool]).output().ok()?;
+    output
+        .status
+        .success()
+        .then(|| PathBuf::from(String::from_utf8_lossy(&output.stdout).trim()))
+}
+
+/// `tool` with the prefix of the archiver `ar`, e.g. `llvm-nm` for `llvm-ar`, if it exists.
+fn sibling_tool(ar: &Path, tool: &str) -> Option<OsString> {
+    let name = ar.file_name()?.to_str()?;
+    let prefix = name.strip_suffix("ar")?;
+    // `<prefix>gcc-ar` wraps `<prefix>ar`, next to `<prefix>objcopy`
+    let prefix = prefix.strip_suffix("gcc-").unwrap_or(prefix);
+    let name = format!("{}{}", prefix, tool);
+    match ar.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        Some(dir) => Some(dir.join(name)).filter(|path| path.exists()),
+        None => find_in_path(&name).map(|_| PathBuf::from(name)),
+    }
+    .map(Into::into)
+}
+
+fn find_in_path(name: &str) -> Option<PathBuf> {
+    env::split_paths(&env::var_os("PATH")?)
+        .map(|dir| dir.join(name))
+        .find(|path| path.is_file())
+}
+
+/// Renames the global symbols defined in the static libraries `libs` of `lib_dir` to
+/// `<prefix><symbol>`, in definitions and references alike, so that they do not clash with
+/// the same symbols built from another witnesscalc runtime. `exports` are renamed to
+/// `<export><export_suffix>` instead, keeping their names with an empty suffix.
+///
+/// Uses the `nm` and `objcopy` of `tools`.
+pub(super) fn prefix_symbols(
+    tools: &SymbolTools,
+    lib_dir: &Path,
+    libs: &[String],
+    prefix: &str,
+    exports: &[String],
+    export_suffix: &str,
+) -> Result<(), BuildError> {
+    let archives = libs
+        .iter()
+        .map(|lib| lib_dir.join(format!("lib{}.a", lib)))
+        .collect::<Vec<_>>();
+
+    let mut redefinitions = BTreeSet::new();
+    for archive in &archives {
+        let output = error::run(
+            Command::new(&tools.nm)
+                .args(["-g", "-P", "--defined-only"])
+                .arg(archive),
+        )?;
+        for line in String::from_utf8_lossy(&output.stdout).lines() {
+            // Archive members are listed as `libname.a[member.o]:`
+            if line.ends_with(':') {
+                continue;
+            }
+            let Some(symbol) = line.split_whitespace().next() else {
+                continue;
+            };
+            // Mach-O symbols carry a leading underscore
+            let exported = exports
+                .iter()
+                .any(|export| symbol == export || symbol.strip_prefix('_') == Some(export));
+            if exported {
+                if !export_suffix.is_empty() {
+                    redefinitions
+                        .insert((symbol.to_string(), format!("{}{}", symbol, export_suffix)));
+                }
+            } else if !symbol.starts_with(prefix) {
+                redefinitions.insert((symbol.to_string(), format!("{}{}", prefix, symbol)));
+            }
+        }
+    }
+
+    let redefinitions_path = lib_dir.join(format!("{}symbols.txt", prefix));
+    fs::write(
+        &redefinitions_path,
+        redefinitions
+            .iter()
+            .map(|(symbol, new_symbol)| format!("{} {}\n", symbol, new_symbol))
+            .collect::<String>(),
+    )
+    .map_err(BuildError::io("Failed to write the symbol redefinitions"))?;
+    for archive in &archives {
+        error::run(
+            Command::new(&tools.objcopy)
+                .arg(format!("--redefine-syms={}", redefinitions_path.display()))
+                .arg(archive),
+        )?;
+    }
+    Ok(())
+}
@@ -0,0 +1,371 @@
+use super::error::BuildError;
+
+/// How the witnesscalc build scripts build for a target triple.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(super) struct TargetConfig {
+    /// Platform argument of `build_gmp.sh`
+    pub(super) gmp_build_target: String,
+    /// Directory of `depends/gmp` that `build_gmp.sh` installs GMP in for that platform
+    pub(super) gmp_lib_folder: String,
+    /// Target of the witnesscalc `Makefile`
+    pub(super) make_target: String,
+    /// GNU triple of a Linux target cross-compiled with its own C/C++ toolchain: GMP is
+    /// configured for it by this crate rather than `build_gmp.sh`, and CMake pointed at it
+    pub(super) cross_host: Option<String>,
+}
+
+impl TargetConfig {
+    /// `CMAKE_SYSTEM_PROCESSOR` of a cross-compiled Linux target.
+    pub(super) fn system_processor(&self) -> Option<&str> {
+        self.cross_host
+            .as_deref()
+            .and_then(|triple| triple.split('-').next())
+    }
+}
+
+/// A target triple witnesscalc is known to build for.
+struct KnownTarget {
+    triple: &'static str,
+    gmp_build_target: &'static str,
+    gmp_lib_folder: &'static str,
+    make_target: &'static str,
+    /// Whether the build scripts only build for this target when it is the host, as their
+    /// `host` platforms use the host compiler and detect the host CPU
+    native_only: bool,
+    /// GNU triple to configure GMP for when cross-compiling to this Linux target
+    gmp_cross_host: Option<&'static str>,
+}
+
+impl KnownTarget {
+    const fn native(triple: &'static str, make_target: &'static str) -> Self {
+        Self {
+            triple,
+            gmp_build_target: "host",
+            gmp_lib_folder: "package",
+            make_target,
+            native_only: true,
+            gmp_cross_host: None,
+        }
+    }
+
+    /// A Linux target, built like the host natively and with its toolchain otherwise.
+    const fn linux(
+        triple: &'static str,
+        gmp_cross_host: &'static str,
+        make_target: &'static str,
+    ) -> Self {
+        Self {
+            triple,
+            gmp_build_target: "host",
+            gmp_lib_folder: "package",
+            make_target,
+            native_only: false,
+            gmp_cross_host: Some(gmp_cross_host),
+        }
+    }
+
+    const fn cross(
+        triple: &'static str,
+        gmp_build_target: &'static str,
+        gmp_lib_folder: &'static str,
+        make_target: &'static str,
+    ) -> Self {
+        Self {
+            triple,
+            gmp_build_target,
+            gmp_lib_folder,
+            make_target,
+            native_only: false,
+            gmp_cross_host: None,
+        }
+    }
+}
+
+/// Supported targets. For the platforms see `build_gmp.sh` and the `Makefile` of witnesscalc.
+static TARGETS: &[KnownTarget] = &[
+    KnownTarget::linux("x86_64-unknown-linux-gnu", "x86_64-linux-gnu", "host"),
+    KnownTarget::linux("x86_64-unknown-linux-musl", "x86_64-linux-musl", "host"),
+    KnownTarget::linux("aarch64-unknown-linux-gnu", "aarch64-linux-gnu", "host"),
+    KnownTarget::linux("aarch64-unknown-linux-musl", "aarch64-linux-musl", "host"),
+    KnownTarget::linux("riscv64gc-unknown-linux-gnu", "riscv64-linux-gnu", "host"),
+    KnownTarget::linux("riscv64gc-unknown-linux-musl", "riscv64-linux-musl", "host"),
+    KnownTarget::native("x86_64-apple-darwin", "host"),
+    // GMP's `host` platform, as `macos_arm64` fails the witnesscalc build that follows
+    KnownTarget::native("aarch64-apple-darwin", "arm64_host"),
+    KnownTarget::cross("aarch64-apple-ios", "ios", "package_ios_arm64", "ios"),
+    KnownTarget::cross(
+        "aarch64-apple-ios-sim",
+        "ios_simulator",
+        "package_iphone_simulator_arm64",
+        "ios_simulator_arm64",
+    ),
+    KnownTarget::cross(
+        "x86_64-apple-ios",
+        "ios_simulator",
+        "package_iphone_simulator_x86_64",
+        "ios_simulator_x86_64",
+    ),
+    KnownTarget::cross(
+        "aarch64-linux-android",
+        "android",
+        "package_android_arm64",
+        "android",
+    ),
+    KnownTarget::cross(
+        "armv7-linux-androideabi",
+        "android",
+        "package_android_arm64",
+        "android",
+    ),
+    KnownTarget::cross(
+        "x86_64-linux-android",
+        "android_x86_64",
+        "package_android_x86_64",
+        "android_x86_64",
+    ),
+    KnownTarget::cross(
+        "i686-linux-android",
+        "android_x86_64",
+        "package_android_x86_64",
+        "android_x86_64",
+    ),
+];
+
+/// Whether witnesscalc has field arithmetic assembly for the architecture of `target`.
+/// Other architectures are built with the portable C++ implementation.
+pub(super) fn has_asm(target: &str) -> bool {
+    matches!(target.split('-').next(), Some("x86_64" | "aarch64"))
+}
+
+/// Looks up how to build for `target` from `host`.
+///
+/// `gmp_build_target` and `make_target` override the platforms of the table. Targets missing
+/// from it are built like the host when they are the host, and fail otherwise unless both
+/// platforms are overridden, rather than producing host libraries that fail to link.
+pub(super) fn resolve(
+    target: &str,
+    host: &str,
+    gmp_build_target: Option<String>,
+    make_target: Option<String>,
+) -> Result<TargetConfig, BuildError> {
+    let known = TARGETS.iter().find(|known| known.triple == target);
+    let cross = target != host;
+    let overridden = gmp_build_target.is_some() && make_target.is_some();
+    let known = match known {
+        Some(known) if known.native_only && cross && !overridden => {
+            return Err(BuildError::Config(format!(
+                "Cross-compiling to {} from {} is not supported, set WITNESSCALC_GMP_TARGET \
+                 and WITNESSCALC_MAKE_TARGET to the witnesscalc platforms to build it with",
+                target, host
+            )))
+        }
+        Some(known) => Some(known),
+        None if cross && !overridden => {
+            return Err(BuildError::Config(format!(
+                "Unsupported target {}, expected one of: {}. Set WITNESSCALC_GMP_TARGET and \
+                 WITNESSCALC_MAKE_TARGET to the witnesscalc platforms to build it with",
+                target,
+                TARGETS
+                    .iter()
+                    .map(|known| known.triple)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )))
+        }
+        None => None,
+    };
+
+    // Overriding the GMP platform builds it with build_gmp.sh again
+    let cross_host = known
+        .filter(|_| cross && gmp_build_target.is_none())
+        .and_then(|known| known.gmp_cross_host)
+        .map(String::from);
+    let gmp_build_target = gmp_build_target.unwrap_or_else(|| {
+        known
+            .map_or("host", |known| known.gmp_build_target)
+            .to_string()
+    });
+    // The folder follows the GMP platform, unless the target picks one of several
+    let gmp_lib_folder = known
+        .filter(|known| known.gmp_build_target == gmp_build_target)
+        .or_else(|| {
+            TARGETS
+                .iter()
+                .find(|known| known.gmp_build_target == gmp_build_target)
+        })
+        .map_or("package", |known| known.gmp_lib_folder)
+        .to_string();
+    let make_target =
+        make_target.unwrap_or_else(|| known.map_or("host", |known| known.make_target).to_string());
+    Ok(TargetConfig {
+        gmp_build_target,
+        gmp_lib_folder,
+        make_target,
+        cross_host,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(
+        gmp_build_target: &str,
+        gmp_lib_folder: &str,
+        make_target: &str,
+        cross_host: Option<&str>,
+    ) -> TargetConfig {
+        TargetConfig {
+            gmp_build_target: gmp_build_target.to_string(),
+            gmp_lib_folder: gmp_lib_folder.to_string(),
+            make_target: make_target.to_string(),
+            cross_host: cross_host.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_resolve() {
+        let linux = "x86_64-unknown-linux-gnu";
+        let macos = "aarch64-apple-darwin";
+        let cases = [
+            // Native
+            (
+                linux,
+                linux,
+                None,
+                None,
+                config("host", "package", "host", None),
+            ),
+            (
+                macos,
+                macos,
+                None,
+                None,
+                config("host", "package", "arm64_host", None),
+            ),
+            // Cross
+            (
+                "aarch64-unknown-linux-gnu",
+                linux,
+                None,
+                None,
+                config("host", "package", "host", Some("aarch64-linux-gnu")),
+            ),
+            (
+                "aarch64-apple-ios",
+                macos,
+                None,
+                None,
+                config("ios", "package_ios_arm64", "ios", None),
+            ),
+            (
+                "x86_64-linux-android",
+                linux,
+                None,
+                None,
+                config(
+                    "android_x86_64",
+                    "package_android_x86_64",
+                    "android_x86_64",
+                    None,
+                ),
+            ),
+            // Unknown, built like the host natively
+            (
+                "powerpc64le-unknown-linux-gnu",
+                "powerpc64le-unknown-linux-gnu",
+                None,
+                None,
+                config("host", "package", "host", None),
+            ),
+            // Overridden
+            (
+                "aarch64-unknown-linux-gnu",
+                linux,
+                Some("android"),
+                None,
+                config("android", "package_android_arm64", "host", None),
+            ),
+            (
+                "aarch64-apple-darwin",
+                "x86_64-apple-darwin",
+                Some("macos_arm64"),
+                Some("arm64_host"),
+                config("macos_arm64", "package", "arm64_host", None),
+            ),
+            (
+                "armv7-unknown-linux-gnueabihf",
+                linux,
+                Some("android"),
+                Some("android"),
+                config("android", "package_android_arm64", "android", None),
+            ),
+        ];
+        for (target, host, gmp_build_target, make_target, expected) in cases {
+            let resolved = resolve(
+                target,
+                host,
+                gmp_build_target.map(String::from),
+                make_target.map(String::from),
+            );
+            assert_eq!(resolved.unwrap(), expected, "{} from {}", target, host);
+        }
+    }
+
+    #[test]
+    fn test_resolve_unsupported() {
+        let cases = [
+            // Unknown cross targets
+            (
+                "armv7-unknown-linux-gnueabihf",
+                "x86_64-unknown-linux-gnu",
+                None,
+                None,
+            ),
+            (
+                "armv7-unknown-linux-gnueabihf",
+                "x86_64-unknown-linux-gnu",
+                Some("android"),
+                None,
+            ),
+            // Native only targets
+            ("x86_64-apple-darwin", "aarch64-apple-darwin", None, None),
+            (
+                "aarch64-apple-darwin",
+                "x86_64-apple-darwin",
+                None,
+                Some("arm64_host"),
+            ),
+        ];
+        for (target, host, gmp_build_target, make_target) in cases {
+            let resolved = resolve(
+                target,
+                host,
+                gmp_build_target.map(String::from),
+                make_target.map(String::from),
+            );
+            assert!(
+                matches!(resolved, Err(BuildError::Config(_))),
+                "{} from {}",
+                target,
+                host
+            );
+        }
+    }
+
+    #[test]
+    fn test_system_processor() {
+        let cross = resolve(
+            "riscv64gc-unknown-linux-gnu",
+            "x86_64-unknown-linux-gnu",
+            None,
+            None,
+        )
+        .unwrap();
+        assert_eq!(cross.system_processor(), Some("riscv64"));
+        assert_eq!(
+            config("host", "package", "host", None).system_processor(),
+            None
+        );
+    }
+}
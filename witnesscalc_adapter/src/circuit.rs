@@ -0,0 +1,482 @@
+use std::{
+    collections::HashMap,
+    ffi::{c_char, c_int, c_ulong, CStr, CString},
+    fmt, fs, io,
+    path::Path,
+};
+
+use anyhow::{anyhow, Result};
+use num_bigint::BigInt;
+use sha2::{Digest, Sha256};
+
+use crate::{convert_inputs_to_json, parse_witness_to_bigints};
+
+/// A circuit declared with [`crate::witness`].
+///
+/// Every invocation of the macro generates a zero-sized `<CircuitName>Circuit` type implementing
+/// this trait, so witness generation can be written generically over circuits.
+pub trait WitnessCalculator {
+    /// The circuit name, as passed to [`crate::witness`].
+    const NAME: &'static str;
+    /// Sizes read from the generated circuit `.cpp` at build time.
+    const METADATA: CircuitMetadata;
+
+    /// The circuit `.dat` file contents.
+    fn circuit_data() -> Result<&'static [u8]>;
+
+    /// Calculates the witness for a JSON input, returning `wtns` bytes.
+    fn calculate(json_input: &str) -> Result<Vec<u8>>;
+
+    /// Calculates the witness for inputs as accepted by [`convert_inputs_to_json`].
+    fn calculate_from_inputs(inputs: HashMap<String, Vec<String>>) -> Result<Vec<u8>> {
+        Self::calculate(&convert_inputs_to_json(inputs))
+    }
+
+    /// Calculates the witness for a JSON input and parses it with [`parse_witness_to_bigints`].
+    fn calculate_bigints(json_input: &str) -> Result<Vec<BigInt>> {
+        Ok(parse_witness_to_bigints(&Self::calculate(json_input)?)?)
+    }
+
+    /// Number of field elements in the witness.
+    fn witness_size() -> usize {
+        Self::METADATA.witness_size
+    }
+
+    /// Number of input signals of the main component.
+    fn input_signal_count() -> usize {
+        Self::METADATA.main_input_signal_count
+    }
+
+    /// A type-erased handle to this circuit.
+    fn circuit() -> Circuit
+    where
+        Self: Sized,
+    {
+        Circuit::of::<Self>()
+    }
+}
+
+/// Where the witness functions of a circuit get its `.dat` file from, as chosen with the
+/// `dat` option of [`crate::witness`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum DatSource {
+    /// Embedded in the binary, the default
+    Embedded,
+    /// Embedded compressed, `dat = compressed`
+    Compressed,
+    /// Loaded at runtime, `dat = runtime`
+    Runtime,
+}
+
+/// A type-erased handle to a [`WitnessCalculator`], for storing different circuits in one collection.
+#[derive(Clone, Copy)]
+pub struct Circuit {
+    name: &'static str,
+    metadata: CircuitMetadata,
+    dat_source: DatSource,
+    circuit_data: fn() -> Result<&'static [u8]>,
+    calculate: fn(&str) -> Result<Vec<u8>>,
+}
+
+impl Circuit {
+    pub const fn of<C: WitnessCalculator>() -> Self {
+        Self {
+            name: C::NAME,
+            metadata: C::METADATA,
+            dat_source: DatSource::Embedded,
+            circuit_data: C::circuit_data,
+            calculate: C::calculate,
+        }
+    }
+
+    /// Sets where the circuit gets its `.dat` file from. Used by [`crate::witness`].
+    #[doc(hidden)]
+    pub const fn with_dat_source(mut self, dat_source: DatSource) -> Self {
+        self.dat_source = dat_source;
+        self
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn dat_source(&self) -> DatSource {
+        self.dat_source
+    }
+
+    pub fn metadata(&self) -> &CircuitMetadata {
+        &self.metadata
+    }
+
+    pub fn circuit_data(&self) -> Result<&'static [u8]> {
+        (self.circuit_data)()
+    }
+
+    pub fn calculate(&self, json_input: &str) -> Result<Vec<u8>> {
+        (self.calculate)(json_input)
+    }
+
+    pub fn calculate_from_inputs(&self, inputs: HashMap<String, Vec<String>>) -> Result<Vec<u8>> {
+        self.calculate(&convert_inputs_to_json(inputs))
+    }
+
+    pub fn calculate_bigints(&self, json_input: &str) -> Result<Vec<BigInt>> {
+        Ok(parse_witness_to_bigints(&self.calculate(json_input)?)?)
+    }
+}
+
+impl fmt::Debug for Circuit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Circuit")
+            .field("name", &self.name)
+            .field("metadata", &self.metadata)
+            .finish()
+    }
+}
+
+/// Sizes of a circuit, as reported by the `get_*` functions of its generated `.cpp`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CircuitMetadata {
+    /// Number of field elements in the witness
+    pub witness_size: usize,
+    /// Index of the first input signal of the main component
+    pub main_input_signal_start: usize,
+    /// Number of input signals of the main component
+    pub main_input_signal_count: usize,
+    /// Number of signals, including intermediate ones
+    pub total_signal_count: usize,
+    pub component_count: usize,
+    pub constant_count: usize,
+    pub io_map_size: usize,
+}
+
+impl CircuitMetadata {
+    const GETTERS: [&'static str; 7] = [
+        "get_size_of_witness",
+        "get_main_input_signal_start",
+        "get_main_input_signal_no",
+        "get_total_signal_no",
+        "get_number_of_components",
+        "get_size_of_constants",
+        "get_size_of_io_map",
+    ];
+
+    /// Builds the metadata from values ordered as in [`CircuitMetadata::to_raw`].
+    #[doc(hidden)]
+    pub const fn from_raw(raw: [usize; 7]) -> Self {
+        Self {
+            witness_size: raw[0],
+            main_input_signal_start: raw[1],
+            main_input_signal_count: raw[2],
+            total_signal_count: raw[3],
+            component_count: raw[4],
+            constant_count: raw[5],
+            io_map_size: raw[6],
+        }
+    }
+
+    pub(crate) fn to_raw(self) -> [usize; 7] {
+        [
+            self.witness_size,
+            self.main_input_signal_start,
+            self.main_input_signal_count,
+            self.total_signal_count,
+            self.component_count,
+            self.constant_count,
+            self.io_map_size,
+        ]
+    }
+
+    /// Reads the sizes from lines like `uint get_size_of_witness() {return 4;}`.
+    /// Getters missing from the source are left at zero.
+    pub(crate) fn parse_cpp(circuit_cpp: &str) -> Self {
+        let mut raw = [0; 7];
+        for line in circuit_cpp
+            .lines()
+            .filter(|line| line.starts_with("uint get_"))
+        {
+            let Some((name, body)) = line["uint ".len()..].split_once("()") else {
+                continue;
+            };
+            let Some(index) = Self::GETTERS.iter().position(|getter| *getter == name) else {
+                continue;
+            };
+            if let Some(value) = body
+                .trim()
+                .strip_prefix("{return ")
+                .and_then(|body| body.strip_suffix(";}"))
+                .and_then(|value| value.trim().parse().ok())
+            {
+                raw[index] = value;
+            }
+        }
+        Self::from_raw(raw)
+    }
+}
+
+/// Signature of the `witnesscalc_<circuit name>` function exported by every circuit library.
+pub type WitnesscalcFn = unsafe extern "C" fn(
+    circuit_buffer: *const c_char,
+    circuit_size: c_ulong,
+    json_buffer: *const c_char,
+    json_size: c_ulong,
+    wtns_buffer: *mut c_char,
+    wtns_size: *mut c_ulong,
+    error_msg: *mut c_char,
+    error_msg_maxsize: c_ulong,
+) -> c_int;
+
+// FFI return codes
+const WITNESSCALC_OK: c_int = 0x0;
+const WITNESSCALC_ERROR: c_int = 0x1;
+const WITNESSCALC_ERROR_SHORT_BUFFER: c_int = 0x2;
+
+/// Stands in for the witnesscalc function of circuits without a library, when the build was
+/// skipped or the circuits are mocked. Never called by the code [`crate::witness`] generates.
+#[doc(hidden)]
+pub unsafe extern "C" fn unlinked_witnesscalc(
+    _circuit_buffer: *const c_char,
+    _circuit_size: c_ulong,
+    _json_buffer: *const c_char,
+    _json_size: c_ulong,
+    _wtns_buffer: *mut c_char,
+    _wtns_size: *mut c_ulong,
+    _error_msg: *mut c_char,
+    _error_msg_maxsize: c_ulong,
+) -> c_int {
+    WITNESSCALC_ERROR
+}
+
+/// The `.dat` file of a circuit, either embedded in the binary or loaded at runtime.
+///
+/// The SHA-256 of the data is computed once on construction so it can be checked
+/// against the hash recorded by `build_and_link` before every witness calculation.
+pub struct CircuitData {
+    bytes: DatBytes,
+    sha256: String,
+}
+
+enum DatBytes {
+    Static(&'static [u8]),
+    Owned(Vec<u8>),
+    #[cfg(feature = "mmap")]
+    Mapped(memmap2::Mmap),
+}
+
+impl CircuitData {
+    /// Wraps circuit data that lives for the whole program, e.g. an `include_bytes!` constant.
+    pub fn from_static(bytes: &'static [u8]) -> Self {
+        Self::new(DatBytes::Static(bytes))
+    }
+
+    /// Takes ownership of circuit data, e.g. a `.dat` file downloaded by the application.
+    pub fn from_bytes(bytes: impl Into<Vec<u8>>) -> Self {
+        Self::new(DatBytes::Owned(bytes.into()))
+    }
+
+    /// Reads the circuit data from a `.dat` file.
+    pub fn from_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(Self::from_bytes(fs::read(path)?))
+    }
+
+    /// Memory-maps the circuit data from a `.dat` file instead of reading it into memory.
+    ///
+    /// # Safety
+    ///
+    /// The file must not be modified or truncated while the returned value is alive,
+    /// see [`memmap2::Mmap::map`].
+    #[cfg(feature = "mmap")]
+    pub unsafe fn map_path(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = fs::File::open(path)?;
+        Ok(Self::new(DatBytes::Mapped(memmap2::Mmap::map(&file)?)))
+    }
+
+    fn new(bytes: DatBytes) -> Self {
+        let mut data = Self {
+            bytes,
+            sha256: String::new(),
+        };
+        data.sha256 = sha256_hex(data.as_bytes());
+        data
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match &self.bytes {
+            DatBytes::Static(bytes) => bytes,
+            DatBytes::Owned(bytes) => bytes,
+            #[cfg(feature = "mmap")]
+            DatBytes::Mapped(mmap) => mmap,
+        }
+    }
+
+    /// Lowercase hex encoded SHA-256 of the circuit data.
+    pub fn sha256(&self) -> &str {
+        &self.sha256
+    }
+
+    /// Checks the circuit data against the hash recorded at build time.
+    pub fn verify(&self, circuit_name: &str, expected_sha256: &str) -> Result<()> {
+        let expected_sha256 = expected_sha256.trim();
+        // Mocked circuits are not built, so there is no hash to check against
+        if cfg!(feature = "mock") && expected_sha256.is_empty() {
+            return Ok(());
+        }
+        if self.sha256 != expected_sha256 {
+            return Err(anyhow!(
+                "Circuit data checksum mismatch for {}: expected sha256 {}, got {}",
+                circuit_name,
+                expected_sha256,
+                self.sha256
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Debug for CircuitData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CircuitData")
+            .field("len", &self.as_bytes().len())
+            .field("sha256", &self.sha256)
+            .finish()
+    }
+}
+
+/// Error of every witness function of a crate whose build skipped compiling the circuits,
+/// on docs.rs or with `WITNESSCALC_SKIP_BUILD`, see [`crate::Build::skip_build`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct NotBuilt {
+    /// Name of the circuit
+    pub circuit: &'static str,
+}
+
+impl fmt::Display for NotBuilt {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Circuit {} was not built, as the build skipped compiling the circuits",
+            self.circuit
+        )
+    }
+}
+
+impl std::error::Error for NotBuilt {}
+
+/// Compresses circuit data for embedding with `witness!(<circuit name>, dat = compressed)`.
+#[cfg(feature = "compressed")]
+pub(crate) fn compress_circuit_data(bytes: &[u8]) -> io::Result<Vec<u8>> {
+    use std::io::Write;
+
+    let mut encoder = flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(bytes)?;
+    encoder.finish()
+}
+
+/// Decompresses circuit data embedded by `witness!(<circuit name>, dat = compressed)`.
+#[doc(hidden)]
+#[cfg(feature = "compressed")]
+pub fn decompress_circuit_data(compressed: &[u8]) -> io::Result<Vec<u8>> {
+    use std::io::Read;
+
+    let mut bytes = Vec::new();
+    flate2::read::DeflateDecoder::new(compressed).read_to_end(&mut bytes)?;
+    Ok(bytes)
+}
+
+pub(crate) fn sha256_hex(bytes: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(bytes))
+}
+
+/// Runs the witness generator of a circuit. Used by the code generated by [`crate::witness`].
+///
+/// With the `mock` feature, returns the witness set with [`crate::mock::set_witness`] instead.
+#[doc(hidden)]
+pub fn calculate_witness(
+    circuit_name: &str,
+    witnesscalc: WitnesscalcFn,
+    circuit_data: &[u8],
+    json_input: &str,
+) -> Result<Vec<u8>> {
+    #[cfg(feature = "mock")]
+    {
+        let _ = (witnesscalc, circuit_data);
+        crate::mock::calculate(circuit_name, json_input)
+    }
+    #[cfg(not(feature = "mock"))]
+    {
+        run_witnesscalc(circuit_name, witnesscalc, circuit_data, json_input)
+    }
+}
+
+#[cfg_attr(feature = "mock", allow(dead_code))]
+fn run_witnesscalc(
+    circuit_name: &str,
+    witnesscalc: WitnesscalcFn,
+    circuit_data: &[u8],
+    json_input: &str,
+) -> Result<Vec<u8>> {
+    println!("Generating witness for circuit {}", circuit_name);
+    unsafe {
+        let json_input = CString::new(json_input)
+            .map_err(|e| anyhow!("Failed to convert JSON input to CString: {}", e))?;
+        let json_size = json_input.as_bytes().len() as c_ulong;
+
+        let circuit_buffer = circuit_data.as_ptr() as *const c_char;
+        let circuit_size = circuit_data.len() as c_ulong;
+
+        let mut error_msg = vec![0u8; 256]; // Error message buffer
+        let error_msg_ptr = error_msg.as_mut_ptr() as *mut c_char;
+
+        let initial_size = (circuit_size as usize) * 8;
+        let mut wtns_buffer: Vec<u8> = vec![0u8; initial_size];
+        let mut wtns_size: c_ulong = initial_size as c_ulong;
+
+        let result = witnesscalc(
+            circuit_buffer,
+            circuit_size,
+            json_input.as_ptr(),
+            json_size,
+            wtns_buffer.as_mut_ptr() as *mut _,
+            &mut wtns_size as *mut _,
+            error_msg_ptr,
+            error_msg.len() as c_ulong,
+        );
+
+        if result == WITNESSCALC_OK {
+            wtns_buffer.truncate(wtns_size as usize);
+            Ok(wtns_buffer)
+        } else if result == WITNESSCALC_ERROR_SHORT_BUFFER {
+            let required_size = wtns_size as usize;
+            println!(
+                "Witness requires {} bytes (estimate {} too small), retrying...",
+                required_size, initial_size
+            );
+            drop(wtns_buffer);
+
+            let mut exact_buffer: Vec<u8> = vec![0u8; required_size];
+            let mut wtns_size: c_ulong = required_size as c_ulong;
+
+            let result = witnesscalc(
+                circuit_buffer,
+                circuit_size,
+                json_input.as_ptr(),
+                json_size,
+                exact_buffer.as_mut_ptr() as *mut _,
+                &mut wtns_size as *mut _,
+                error_msg_ptr,
+                error_msg.len() as c_ulong,
+            );
+
+            if result != WITNESSCALC_OK {
+                let error_string = CStr::from_ptr(error_msg_ptr).to_string_lossy().into_owned();
+                return Err(anyhow!("Witness generation failed: {}", error_string));
+            }
+
+            exact_buffer.truncate(wtns_size as usize);
+            Ok(exact_buffer)
+        } else {
+            let error_string = CStr::from_ptr(error_msg_ptr).to_string_lossy().into_owned();
+            Err(anyhow!("Witness generation failed: {}", error_string))
+        }
+    }
+}
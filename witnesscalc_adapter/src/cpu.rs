@@ -0,0 +1,64 @@
+use std::sync::{
+    atomic::{AtomicU8, Ordering},
+    OnceLock,
+};
+
+/// A variant of the field arithmetic witnesses are calculated with.
+///
+/// Builds with CPU dispatch (`Build::cpu_dispatch`) link two variants of every circuit on
+/// x86_64: one with the field arithmetic assembly, which needs the ADX and BMI2 instructions,
+/// and a baseline one with circom's generic C++ implementation. The variant is picked when
+/// a witness is calculated, see [`field_arithmetic`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FieldArithmetic {
+    /// The assembly implementation, requiring ADX and BMI2 on x86_64
+    Optimized,
+    /// Circom's generic C++ implementation, running on any CPU
+    Baseline,
+}
+
+const AUTO: u8 = 0;
+const OPTIMIZED: u8 = 1;
+const BASELINE: u8 = 2;
+
+static FORCED: AtomicU8 = AtomicU8::new(AUTO);
+
+/// The field arithmetic witnesses are calculated with: the one set with
+/// [`set_field_arithmetic`], or the fastest one the CPU supports.
+///
+/// Builds without CPU dispatch only link one variant, which is used regardless.
+pub fn field_arithmetic() -> FieldArithmetic {
+    match FORCED.load(Ordering::Relaxed) {
+        OPTIMIZED => FieldArithmetic::Optimized,
+        BASELINE => FieldArithmetic::Baseline,
+        _ => detected_field_arithmetic(),
+    }
+}
+
+/// Forces the field arithmetic of the following witness calculations, e.g. to test both
+/// variants, or restores the detected one with `None`.
+///
+/// Forcing [`FieldArithmetic::Optimized`] on a CPU without ADX or BMI2 crashes with an
+/// illegal instruction.
+pub fn set_field_arithmetic(field_arithmetic: Option<FieldArithmetic>) {
+    let forced = match field_arithmetic {
+        None => AUTO,
+        Some(FieldArithmetic::Optimized) => OPTIMIZED,
+        Some(FieldArithmetic::Baseline) => BASELINE,
+    };
+    FORCED.store(forced, Ordering::Relaxed);
+}
+
+/// The fastest field arithmetic the CPU supports.
+pub fn detected_field_arithmetic() -> FieldArithmetic {
+    static DETECTED: OnceLock<FieldArithmetic> = OnceLock::new();
+    *DETECTED.get_or_init(|| {
+        #[cfg(target_arch = "x86_64")]
+        {
+            if !std::is_x86_feature_detected!("adx") || !std::is_x86_feature_detected!("bmi2") {
+                return FieldArithmetic::Baseline;
+            }
+        }
+        FieldArithmetic::Optimized
+    })
+}
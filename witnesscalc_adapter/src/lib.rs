@@ -1,363 +1,289 @@
 pub use paste;
 pub use serde_json;
-use std::{
-    env, fs,
-    path::{Path, PathBuf},
-    process::Command,
-};
 
+mod build;
+pub mod circuit;
 pub mod convert_type;
+pub mod cpu;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod registry;
+pub use build::{build_and_link, Backend, Build, BuildError, BuildOutput, BuiltCircuit, Gmp};
+pub use circuit::*;
 pub use convert_type::*;
+pub use cpu::{detected_field_arithmetic, field_arithmetic, set_field_arithmetic, FieldArithmetic};
+pub use registry::{registry, Registry};
 
 #[doc(hidden)]
 pub mod __macro_deps {
     pub use anyhow;
+    pub use inventory;
+}
+
+/// Whether [`witness`] binds to the mock of the `mock` feature rather than circuit libraries.
+#[doc(hidden)]
+pub const __MOCK: bool = cfg!(feature = "mock");
+
+/// Keeps the items [`witness`] generates from the circuit libraries and the files the build
+/// writes next to them, unless the build was skipped or the circuits are mocked.
+#[cfg(not(feature = "mock"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_linked {
+    ($($item:item)*) => {
+        $(
+            #[cfg(not(witnesscalc_stub))]
+            $item
+        )*
+    };
+}
+
+#[cfg(feature = "mock")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_linked {
+    ($($item:item)*) => {};
+}
+
+/// Keeps the placeholders of the items [`__if_linked`] drops.
+#[cfg(not(feature = "mock"))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_unlinked {
+    ($($item:item)*) => {
+        $(
+            #[cfg(witnesscalc_stub)]
+            $item
+        )*
+    };
+}
+
+#[cfg(feature = "mock")]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __if_unlinked {
+    ($($item:item)*) => {
+        $($item)*
+    };
 }
 
 /// Macro to generate a witness for a given circuit
+///
+/// Generates a `<circuit name>_witness` function and a zero-sized `<CircuitName>Circuit` type
+/// implementing [`WitnessCalculator`], and adds the circuit to the [`registry()`].
+///
+/// By default the circuit `.dat` file is embedded in the binary with `include_bytes!`.
+/// Use `witness!(<circuit name>, dat = runtime)` to ship the `.dat` file separately: the
+/// generated `<circuit name>_load_dat` function installs a [`CircuitData`] after checking it
+/// against the hash recorded by `build_and_link`, and `<circuit name>_witness_with_dat`
+/// calculates a witness with explicitly provided circuit data.
+///
+/// With the `compressed` feature, `witness!(<circuit name>, dat = compressed)` embeds a
+/// deflate-compressed copy of the `.dat` file that is decompressed once on first use.
+///
+/// When the build skipped compiling the circuits (see [`Build::skip_build`]), the generated
+/// items keep their signatures but every witness function fails with [`NotBuilt`]. With the
+/// `mock` feature, they return the witnesses set with `crate::mock::set_witness` instead.
 #[macro_export]
 macro_rules! witness {
     ($x: ident) => {
+        $crate::witness!($x, dat = embedded);
+    };
+    ($x: ident, dat = embedded) => {
         $crate::paste::paste! {
-            #[allow(non_upper_case_globals)]
-            const [<$x _CIRCUIT_DATA>]: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/witnesscalc/src/", stringify!($x), ".dat"));
-            #[link(name = "witnesscalc_" [<$x>], kind = "static")]
-            extern "C" {
-                fn [<witnesscalc_ $x>](
-                    circuit_buffer: *const std::os::raw::c_char,
-                    circuit_size: std::ffi::c_ulong,
-                    json_buffer: *const std::os::raw::c_char,
-                    json_size: std::ffi::c_ulong,
-                    wtns_buffer: *mut std::os::raw::c_char,
-                    wtns_size: *mut std::ffi::c_ulong,
-                    error_msg: *mut std::os::raw::c_char,
-                    error_msg_maxsize: std::ffi::c_ulong,
-                ) -> std::ffi::c_int;
+            $crate::__if_linked! {
+                #[allow(non_upper_case_globals)]
+                const [<$x _CIRCUIT_DATA>]: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/witnesscalc/src/", stringify!($x), ".dat"));
+            }
+            $crate::__if_unlinked! {
+                #[allow(non_upper_case_globals)]
+                const [<$x _CIRCUIT_DATA>]: &[u8] = &[];
             }
-        }
-        $crate::paste::item! {
-            pub fn [<$x _witness>](json_input: &str) -> $crate::__macro_deps::anyhow::Result<Vec<u8>> {
-                // FFI return codes
-                const WITNESSCALC_OK: std::ffi::c_int = 0x0;
-                const WITNESSCALC_ERROR_SHORT_BUFFER: std::ffi::c_int = 0x2;
-
-                println!("Generating witness for circuit {}", stringify!($x));
-                unsafe {
-                    let json_input = std::ffi::CString::new(json_input).map_err(|e| $crate::__macro_deps::anyhow::anyhow!("Failed to convert JSON input to CString: {}", e))?;
-                    let json_size = json_input.as_bytes().len() as std::ffi::c_ulong;
-
-                    let circuit_buffer = [<$x _CIRCUIT_DATA>].as_ptr() as *const std::ffi::c_char;
-                    let circuit_size = [<$x _CIRCUIT_DATA>].len() as std::ffi::c_ulong;
-
-                    let mut error_msg = vec![0u8; 256]; // Error message buffer
-                    let error_msg_ptr = error_msg.as_mut_ptr() as *mut std::ffi::c_char;
-
-                    let initial_size = (circuit_size as usize) * 8;
-                    let mut wtns_buffer: Vec<u8> = Vec::with_capacity(initial_size);
-                    wtns_buffer.set_len(initial_size);
-                    let mut wtns_size: std::ffi::c_ulong = initial_size as std::ffi::c_ulong;
-
-                    let result = [<witnesscalc_ $x>](
-                        circuit_buffer,
-                        circuit_size,
-                        json_input.as_ptr(),
-                        json_size,
-                        wtns_buffer.as_mut_ptr() as *mut _,
-                        &mut wtns_size as *mut _,
-                        error_msg_ptr,
-                        error_msg.len() as u64,
-                    );
-
-                    if result == WITNESSCALC_OK {
-                        wtns_buffer.truncate(wtns_size as usize);
-                        Ok(wtns_buffer)
-                    } else if result == WITNESSCALC_ERROR_SHORT_BUFFER {
-                        let required_size = wtns_size as usize;
-                        println!("Witness requires {} bytes (estimate {} too small), retrying...", required_size, initial_size);
-                        drop(wtns_buffer);
-
-                        let mut exact_buffer: Vec<u8> = Vec::with_capacity(required_size);
-                        exact_buffer.set_len(required_size);
-                        let mut wtns_size: std::ffi::c_ulong = required_size as std::ffi::c_ulong;
-
-                        let result = [<witnesscalc_ $x>](
-                            circuit_buffer,
-                            circuit_size,
-                            json_input.as_ptr(),
-                            json_size,
-                            exact_buffer.as_mut_ptr() as *mut _,
-                            &mut wtns_size as *mut _,
-                            error_msg_ptr,
-                            error_msg.len() as u64,
-                        );
 
-                        if result != WITNESSCALC_OK {
-                            let error_string = std::ffi::CStr::from_ptr(error_msg_ptr)
-                                .to_string_lossy()
-                                .into_owned();
-                            return Err($crate::__macro_deps::anyhow::anyhow!("Witness generation failed: {}", error_string));
+            fn [<$x _circuit_data>]() -> $crate::__macro_deps::anyhow::Result<&'static [u8]> {
+                [<$x _built>]()?;
+                Ok([<$x _CIRCUIT_DATA>])
+            }
+        }
+        $crate::__witness_common!($x, Embedded);
+    };
+    ($x: ident, dat = compressed) => {
+        $crate::paste::paste! {
+            $crate::__if_linked! {
+                #[allow(non_upper_case_globals)]
+                const [<$x _COMPRESSED_CIRCUIT_DATA>]: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/witnesscalc/src/", stringify!($x), ".dat.deflate"));
+                #[allow(non_upper_case_globals)]
+                static [<$x _CIRCUIT_DATA>]: std::sync::OnceLock<Vec<u8>> = std::sync::OnceLock::new();
+
+                fn [<$x _circuit_data>]() -> $crate::__macro_deps::anyhow::Result<&'static [u8]> {
+                    [<$x _built>]()?;
+                    match [<$x _CIRCUIT_DATA>].get() {
+                        Some(circuit_data) => Ok(circuit_data),
+                        None => {
+                            let circuit_data = $crate::decompress_circuit_data([<$x _COMPRESSED_CIRCUIT_DATA>])
+                                .map_err(|e| $crate::__macro_deps::anyhow::anyhow!("Failed to decompress circuit data: {}", e))?;
+                            Ok([<$x _CIRCUIT_DATA>].get_or_init(|| circuit_data))
                         }
-
-                        exact_buffer.truncate(wtns_size as usize);
-                        Ok(exact_buffer)
-                    } else {
-                        let error_string = std::ffi::CStr::from_ptr(error_msg_ptr)
-                            .to_string_lossy()
-                            .into_owned();
-                        Err($crate::__macro_deps::anyhow::anyhow!("Witness generation failed: {}", error_string))
                     }
                 }
             }
+            $crate::__if_unlinked! {
+                fn [<$x _circuit_data>]() -> $crate::__macro_deps::anyhow::Result<&'static [u8]> {
+                    [<$x _built>]()?;
+                    Ok(&[])
+                }
+            }
         }
+        $crate::__witness_common!($x, Compressed);
     };
-}
-
-const WITNESSCALC_BUILD_SCRIPT: &str = include_str!("../clone_witnesscalc.sh");
-
-pub fn build_and_link(circuits_dir: &str) {
-    let target = env::var("TARGET").expect("Cargo did not provide the TARGET environment variable");
-
-    let out_dir = env::var("OUT_DIR").expect("OUT_DIR not set");
-    let lib_dir = Path::new(&out_dir)
-        .join("witnesscalc")
-        .join("package")
-        .join("lib");
-
-    if !Path::is_dir(Path::new(circuits_dir)) {
-        panic!("circuits_dir must be a directory");
-    }
-    println!("cargo:rerun-if-changed={}", circuits_dir);
+    ($x: ident, dat = runtime) => {
+        $crate::paste::paste! {
+            $crate::__if_linked! {
+                #[allow(non_upper_case_globals)]
+                const [<$x _CIRCUIT_DATA_SHA256>]: &str = include_str!(concat!(env!("OUT_DIR"), "/witnesscalc/src/", stringify!($x), ".dat.sha256"));
+            }
+            $crate::__if_unlinked! {
+                #[allow(non_upper_case_globals)]
+                const [<$x _CIRCUIT_DATA_SHA256>]: &str = "";
+            }
+            #[allow(non_upper_case_globals)]
+            static [<$x _LOADED_CIRCUIT_DATA>]: std::sync::OnceLock<$crate::CircuitData> = std::sync::OnceLock::new();
+
+            /// Installs the circuit data used by the witness function, after checking its hash.
+            pub fn [<$x _load_dat>](circuit_data: $crate::CircuitData) -> $crate::__macro_deps::anyhow::Result<()> {
+                [<$x _built>]()?;
+                circuit_data.verify(stringify!($x), [<$x _CIRCUIT_DATA_SHA256>])?;
+                // Data matching the build time hash is interchangeable, so a second load is a no-op
+                let _ = [<$x _LOADED_CIRCUIT_DATA>].set(circuit_data);
+                Ok(())
+            }
 
-    let witnesscalc_path = Path::new(&out_dir).join(Path::new("witnesscalc"));
-    // If the witnesscalc repo is not cloned, clone it
-    if !witnesscalc_path.exists() {
-        let witnesscalc_script_path = Path::new(&out_dir).join(Path::new("clone_witnesscalc.sh"));
-        fs::write(&witnesscalc_script_path, WITNESSCALC_BUILD_SCRIPT)
-            .expect("Failed to write build script");
-        Command::new("sh")
-            .arg(witnesscalc_script_path.to_str().unwrap())
-            .spawn()
-            .expect("Failed to spawn witnesscalc build")
-            .wait()
-            .expect("witnesscalc build errored");
-    }
+            pub fn [<$x _witness_with_dat>](circuit_data: &$crate::CircuitData, json_input: &str) -> $crate::__macro_deps::anyhow::Result<Vec<u8>> {
+                [<$x _built>]()?;
+                circuit_data.verify(stringify!($x), [<$x _CIRCUIT_DATA_SHA256>])?;
+                $crate::calculate_witness(stringify!($x), [<$x _witnesscalc_fn>](), circuit_data.as_bytes(), json_input)
+            }
 
-    println!("Detected target: {}", target);
-    //For possible options see witnesscalc/build_gmp.sh
-    let gmp_build_target = match target.as_str() {
-        "aarch64-apple-ios" => "ios",
-        "aarch64-apple-ios-sim" => "ios_simulator",
-        "x86_64-apple-ios" => "ios_simulator",
-        "x86_64-linux-android" => "android_x86_64",
-        "i686-linux-android" => "android_x86_64",
-        "armv7-linux-androideabi" => "android",
-        "aarch64-linux-android" => "android",
-        "aarch64-apple-darwin" => "host", //Use "host" for M Macs, macos_arm64 would fail the subsequent build
-        _ => "host",
+            fn [<$x _circuit_data>]() -> $crate::__macro_deps::anyhow::Result<&'static [u8]> {
+                [<$x _built>]()?;
+                let circuit_data = [<$x _LOADED_CIRCUIT_DATA>].get().ok_or_else(|| {
+                    $crate::__macro_deps::anyhow::anyhow!(
+                        "Circuit data for {} is not loaded, call {} first",
+                        stringify!($x),
+                        stringify!([<$x _load_dat>])
+                    )
+                })?;
+                Ok(circuit_data.as_bytes())
+            }
+        }
+        $crate::__witness_common!($x, Runtime);
     };
+}
 
-    let gmp_lib_folder = match target.as_str() {
-        "aarch64-apple-ios" => "package_ios_arm64",
-        "aarch64-apple-ios-sim" => "package_iphone_simulator_arm64",
-        "x86_64-apple-ios" => "package_iphone_simulator_x86_64",
-        "x86_64-linux-android" => "package_android_x86_64",
-        "i686-linux-android" => "package_android_x86_64",
-        "armv7-linux-androideabi" => "package_android_arm64",
-        "aarch64-linux-android" => "package_android_arm64",
-        _ => "package",
-    };
-    //For possible options see witnesscalc/Makefile
-    let witnesscalc_build_target = match target.as_str() {
-        "aarch64-apple-ios" => "ios",
-        "aarch64-apple-ios-sim" => "ios_simulator_arm64",
-        "x86_64-apple-ios" => "ios_simulator_x86_64",
-        "x86_64-linux-android" => "android_x86_64",
-        "i686-linux-android" => "android_x86_64",
-        "armv7-linux-androideabi" => "android",
-        "aarch64-linux-android" => "android",
-        "aarch64-apple-darwin" => "arm64_host",
-        _ => "host",
-    };
+/// Items shared by every `.dat` mode of [`witness`], built on top of `<circuit name>_circuit_data`.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __witness_common {
+    ($x: ident, $dat_source: ident) => {
+        $crate::__witness_ffi!($x);
+        $crate::paste::paste! {
+            pub fn [<$x _witness>](json_input: &str) -> $crate::__macro_deps::anyhow::Result<Vec<u8>> {
+                // Fails first when the circuit was not built
+                let circuit_data = [<$x _circuit_data>]()?;
+                $crate::calculate_witness(stringify!($x), [<$x _witnesscalc_fn>](), circuit_data, json_input)
+            }
 
-    // If the witnesscalc library is not built, build it
-    let gmp_dir = witnesscalc_path.join("depends").join("gmp");
-    let target_dir = gmp_dir.join(gmp_lib_folder);
-    if !target_dir.exists() {
-        Command::new("bash")
-            .current_dir(&witnesscalc_path)
-            .arg("./build_gmp.sh")
-            .arg(gmp_build_target)
-            .spawn()
-            .expect("Failed to spawn build_gmp.sh")
-            .wait()
-            .expect("build_gmp.sh errored");
-    }
+            #[derive(Clone, Copy, Debug, Default)]
+            pub struct [<$x:camel Circuit>];
 
-    //find all the .cpp files in the circuits_dir
-    let circuit_files = fs::read_dir(circuits_dir)
-        .expect("Failed to read circuits directory")
-        .map(|entry| entry.unwrap().path())
-        .filter(|path| path.extension().is_some() && path.extension().unwrap() == "cpp")
-        .collect::<Vec<_>>();
+            impl $crate::WitnessCalculator for [<$x:camel Circuit>] {
+                const NAME: &'static str = stringify!($x);
+                $crate::__if_linked! {
+                    const METADATA: $crate::CircuitMetadata = $crate::CircuitMetadata::from_raw(
+                        include!(concat!(env!("OUT_DIR"), "/witnesscalc/src/", stringify!($x), ".meta")),
+                    );
+                }
+                $crate::__if_unlinked! {
+                    const METADATA: $crate::CircuitMetadata = $crate::CircuitMetadata::from_raw([0; 7]);
+                }
 
-    let mut v2_1_0_circuit_files: Vec<PathBuf> = Vec::new();
-    let mut v2_2_0_circuit_files: Vec<PathBuf> = Vec::new();
+                fn circuit_data() -> $crate::__macro_deps::anyhow::Result<&'static [u8]> {
+                    [<$x _circuit_data>]()
+                }
 
-    // Copy each circuit .cpp and .dat into witnesscalc/src, replacing any existing files
-    circuit_files.iter().for_each(|path| {
-        let circuit_name = path.file_stem().unwrap().to_str().unwrap();
-        let circuit_dat = path.with_extension("dat");
-        let circuit_dat_name = circuit_dat.file_name().unwrap().to_str().unwrap();
-        let circuit_dat_dest = witnesscalc_path.join("src").join(circuit_dat_name);
-        fs::copy(&circuit_dat, &circuit_dat_dest).expect("Failed to copy circuit .dat file");
-        //For each .cpp file, do the following: find the last include statement (should be #include "calcwit.hpp") and insert the following on the next line: namespace CIRCUIT_NAME {. Then, insert the closing } at the end of the file:
-        let circuit_cpp = fs::read_to_string(path).expect("Failed to read circuit .cpp file");
-        let circuit_cpp = circuit_cpp.replace(
-            "#include \"calcwit.hpp\"",
-            "#include \"calcwit.hpp\"\nnamespace CIRCUIT_NAME {",
-        );
-        let circuit_cpp = circuit_cpp + "\n}";
-        let circuit_cpp_name = witnesscalc_path.join("src").join(circuit_name);
-        let circuit_cpp_dest = circuit_cpp_name.with_extension("cpp");
-        fs::write(&circuit_cpp_dest, &circuit_cpp).expect("Failed to write circuit .cpp file");
+                fn calculate(json_input: &str) -> $crate::__macro_deps::anyhow::Result<Vec<u8>> {
+                    [<$x _witness>](json_input)
+                }
+            }
 
-        let circuit_cpp_str = &circuit_cpp;
-        if circuit_cpp_str.contains("uint get_size_of_bus_field_map() {return 0;}") {
-            v2_2_0_circuit_files.push(path.clone());
-        } else {
-            v2_1_0_circuit_files.push(path.clone());
+            $crate::__macro_deps::inventory::submit! {
+                $crate::Circuit::of::<[<$x:camel Circuit>]>()
+                    .with_dat_source($crate::DatSource::$dat_source)
+            }
         }
-    });
-
-    build_for_circuits_with_different_versions(
-        &v2_1_0_circuit_files,
-        &witnesscalc_path,
-        &witnesscalc_build_target,
-    );
-    if v2_2_0_circuit_files.len() > 0 {
-        Command::new("git")
-            .arg("checkout")
-            .arg("v2.2.0")
-            .current_dir(&witnesscalc_path)
-            .spawn()
-            .expect("Failed to spawn git checkout v2.2.0")
-            .wait()
-            .expect("git checkout v2.2.0 errored");
-        build_for_circuits_with_different_versions(
-            &v2_2_0_circuit_files,
-            &witnesscalc_path,
-            &witnesscalc_build_target,
-        );
-    }
-
-    // Link the C++ standard library. This is necessary for Rust tests to run on the host,
-    // non-host targets may require a specific way of linking (e.g., through linking flags in xcode)
-    #[cfg(target_os = "macos")]
-    {
-        println!("cargo:rustc-link-lib=c++"); // macOS default
-    }
-    #[cfg(not(target_os = "macos"))]
-    {
-        println!("cargo:rustc-link-lib=stdc++"); // Linux or other platforms
-    }
-    // Link the gmp and fr libraries
-    println!("cargo:rustc-link-lib=static=gmp");
-    println!("cargo:rustc-link-lib=static=fr");
-    // Specify the path to the witnesscalc library for the linker
-    println!(
-        "cargo:rustc-link-search=native={}",
-        lib_dir.to_string_lossy()
-    );
-
-    if !(env::var("CARGO_CFG_TARGET_OS").unwrap().contains("ios")
-        || env::var("CARGO_CFG_TARGET_OS").unwrap().contains("android"))
-    {
-        println!("cargo:rustc-link-lib=dylib=fr");
-        println!("cargo:rustc-link-lib=dylib=gmp");
-    }
+    };
 }
 
-fn build_for_circuits_with_different_versions(
-    circuit_files: &Vec<PathBuf>,
-    witnesscalc_path: &Path,
-    witnesscalc_build_target: &str,
-) {
-    circuit_files.iter().for_each(|path| {
-        let circuit_name = path.file_stem().unwrap().to_str().unwrap();
-        //Find a witnesscalc_template.cpp template file in the src. Replace all the @CIRCUIT_NAME@ inside it with the circuit name and write it to the src directory, replacing "template" in the name with the circuit name
-        let template_path = witnesscalc_path
-            .join("src")
-            .join("witnesscalc_template.cpp");
-        let template = fs::read_to_string(&template_path).expect("Failed to read template file");
-        let template = template.replace("@CIRCUIT_NAME@", circuit_name);
-        let template_dest = witnesscalc_path
-            .join("src")
-            .join(format!("witnesscalc_{}.cpp", circuit_name));
-        fs::write(&template_dest, template).expect("Failed to write the templated .cpp file");
-        //Find a witnesscalc_template.h template file in the src. Replace all the @CIRCUIT_NAME@ inside it with the circuit name, @CIRCUIT_NAME_CAPS@ with the capitalized name, and write it to the src directory, replacing "template" in the name with the circuit name
-        let template_path = witnesscalc_path.join("src").join("witnesscalc_template.h");
-        let template = fs::read_to_string(&template_path).expect("Failed to read template file");
-        let template = template
-            .replace("@CIRCUIT_NAME@", circuit_name)
-            .replace("@CIRCUIT_NAME_CAPS@", &circuit_name.to_uppercase());
-        let template_dest = witnesscalc_path
-            .join("src")
-            .join(format!("witnesscalc_{}.h", circuit_name));
-        fs::write(&template_dest, template).expect("Failed to write the templated .h file");
-    });
-
-    //the circuit name list would look like "circuit1;circuit2;circuit3"
-    let circuit_names = circuit_files
-        .iter()
-        .map(|path| path.file_stem().unwrap().to_str().unwrap())
-        .collect::<Vec<_>>();
-
-    let circuit_names_semicolon = circuit_names.join(";");
-
-    let make_process = Command::new("make")
-        .env("CIRCUIT_NAMES", circuit_names_semicolon)
-        .arg(witnesscalc_build_target)
-        .current_dir(&witnesscalc_path)
-        .output()
-        .expect("Failed to execute make arm64_host");
-
-    if !make_process.status.success() {
-        eprintln!(
-            "Make command failed with exit code: {}",
-            make_process.status
-        );
-        eprintln!("stdout: {}", String::from_utf8_lossy(&make_process.stdout));
-        eprintln!("stderr: {}", String::from_utf8_lossy(&make_process.stderr));
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __witness_ffi {
+    ($x: ident) => {
+        $crate::paste::paste! {
+            $crate::__if_linked! {
+                #[link(name = "witnesscalc_" [<$x>], kind = "static")]
+                extern "C" {
+                    fn [<witnesscalc_ $x>](
+                        circuit_buffer: *const std::os::raw::c_char,
+                        circuit_size: std::ffi::c_ulong,
+                        json_buffer: *const std::os::raw::c_char,
+                        json_size: std::ffi::c_ulong,
+                        wtns_buffer: *mut std::os::raw::c_char,
+                        wtns_size: *mut std::ffi::c_ulong,
+                        error_msg: *mut std::os::raw::c_char,
+                        error_msg_maxsize: std::ffi::c_ulong,
+                    ) -> std::ffi::c_int;
+                }
 
-        // Check if any of the required libraries were actually built despite the error
-        let lib_dir = witnesscalc_path.join("package").join("lib");
-        let mut all_libs_exist = true;
+                // Set by `build_and_link` when it also built the baseline variant of the circuit
+                #[cfg(witnesscalc_cpu_dispatch)]
+                #[link(name = "witnesscalc_" [<$x>] "_baseline", kind = "static")]
+                extern "C" {
+                    fn [<witnesscalc_ $x _baseline>](
+                        circuit_buffer: *const std::os::raw::c_char,
+                        circuit_size: std::ffi::c_ulong,
+                        json_buffer: *const std::os::raw::c_char,
+                        json_size: std::ffi::c_ulong,
+                        wtns_buffer: *mut std::os::raw::c_char,
+                        wtns_size: *mut std::ffi::c_ulong,
+                        error_msg: *mut std::os::raw::c_char,
+                        error_msg_maxsize: std::ffi::c_ulong,
+                    ) -> std::ffi::c_int;
+                }
+            }
 
-        for circuit_name in &circuit_names {
-            let lib_path = lib_dir.join(format!("libwitnesscalc_{}.a", circuit_name));
-            if !lib_path.exists() {
-                eprintln!("Warning: Library {} was not built", lib_path.display());
-                all_libs_exist = false;
+            // Fails with `NotBuilt` when there is no circuit library to call
+            fn [<$x _built>]() -> $crate::__macro_deps::anyhow::Result<()> {
+                if cfg!(witnesscalc_stub) && !$crate::__MOCK {
+                    return Err($crate::NotBuilt { circuit: stringify!($x) }.into());
+                }
+                Ok(())
             }
-        }
 
-        if !all_libs_exist {
-            panic!("Make command failed and required libraries are missing");
-        } else {
-            eprintln!("Warning: Make command failed but required libraries exist. Continuing...");
+            $crate::__if_linked! {
+                // The witnesscalc entry point of the selected field arithmetic variant
+                fn [<$x _witnesscalc_fn>]() -> $crate::WitnesscalcFn {
+                    #[cfg(witnesscalc_cpu_dispatch)]
+                    if $crate::field_arithmetic() == $crate::FieldArithmetic::Baseline {
+                        return [<witnesscalc_ $x _baseline>];
+                    }
+                    [<witnesscalc_ $x>]
+                }
+            }
+            $crate::__if_unlinked! {
+                fn [<$x _witnesscalc_fn>]() -> $crate::WitnesscalcFn {
+                    $crate::unlinked_witnesscalc
+                }
+            }
         }
-    }
-
-    // Link the witnesscalc library for the circuit
-    circuit_names.iter().for_each(|circuit_name| {
-        println!("cargo:rustc-link-lib=static=witnesscalc_{}", circuit_name);
-    });
-
-    if !(env::var("CARGO_CFG_TARGET_OS").unwrap().contains("ios")
-        || env::var("CARGO_CFG_TARGET_OS").unwrap().contains("android"))
-    {
-        circuit_names.iter().for_each(|circuit_name| {
-            println!("cargo:rustc-link-lib=dylib=witnesscalc_{}", circuit_name);
-        });
-    }
+    };
 }
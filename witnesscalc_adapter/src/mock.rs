@@ -0,0 +1,170 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use anyhow::{anyhow, Result};
+use num_bigint::{BigInt, Sign};
+
+/// Computes the witness of a mocked circuit from its JSON inputs.
+type WitnessFn = dyn Fn(&str) -> Result<Vec<BigInt>> + Send + Sync;
+
+/// Order of the BN254 scalar field, the field of the witnesses circom generates by default.
+const BN254_PRIME: &str =
+    "21888242871839275222246405745257518193163237082183843637547542222341185880369";
+
+/// Size in bytes of an encoded field element.
+const FIELD_SIZE: usize = 32;
+
+fn mocks() -> &'static RwLock<HashMap<String, Arc<WitnessFn>>> {
+    static MOCKS: OnceLock<RwLock<HashMap<String, Arc<WitnessFn>>>> = OnceLock::new();
+    MOCKS.get_or_init(Default::default)
+}
+
+/// Makes the witness functions of `circuit` return `witness`, whatever their inputs.
+///
+/// With the `mock` feature, the functions generated by [`crate::witness`] call no witness
+/// generator: they return the witness set for their circuit, encoded like a `.wtns` file.
+pub fn set_witness(circuit: &str, witness: Vec<BigInt>) {
+    set_witness_fn(circuit, move |_| Ok(witness.clone()));
+}
+
+/// Makes the witness functions of `circuit` return the witness `witness_fn` computes from
+/// their JSON inputs, e.g. to fail on invalid inputs like the real circuit.
+pub fn set_witness_fn(
+    circuit: &str,
+    witness_fn: impl Fn(&str) -> Result<Vec<BigInt>> + Send + Sync + 'static,
+) {
+    mocks()
+        .write()
+        .unwrap()
+        .insert(circuit.to_string(), Arc::new(witness_fn));
+}
+
+/// Removes the witness set for `circuit`, so its witness functions fail again.
+pub fn clear_witness(circuit: &str) {
+    mocks().write().unwrap().remove(circuit);
+}
+
+/// Encodes field elements of the BN254 scalar field as a `.wtns` file, as witnesscalc
+/// returns them. Elements outside the field, including negative ones, are reduced into it.
+pub fn encode_wtns(witness: &[BigInt]) -> Vec<u8> {
+    let prime = BN254_PRIME.parse::<BigInt>().unwrap();
+    let le_bytes = |value: &BigInt| {
+        let (_, mut bytes) = value.to_bytes_le();
+        bytes.resize(FIELD_SIZE, 0);
+        bytes
+    };
+    let field_bytes = |value: &BigInt| {
+        let mut value = value % &prime;
+        if value.sign() == Sign::Minus {
+            value += &prime;
+        }
+        le_bytes(&value)
+    };
+
+    let mut wtns = Vec::with_capacity(64 + witness.len() * FIELD_SIZE);
+    wtns.extend_from_slice(b"wtns");
+    // Version and number of sections
+    wtns.extend_from_slice(&2u32.to_le_bytes());
+    wtns.extend_from_slice(&2u32.to_le_bytes());
+    // [section 1] field element size, prime and number of witness values
+    wtns.extend_from_slice(&1u32.to_le_bytes());
+    wtns.extend_from_slice(&(4 + FIELD_SIZE as u64 + 4).to_le_bytes());
+    wtns.extend_from_slice(&(FIELD_SIZE as u32).to_le_bytes());
+    wtns.extend_from_slice(&le_bytes(&prime));
+    wtns.extend_from_slice(&(witness.len() as u32).to_le_bytes());
+    // [section 2] witness values
+    wtns.extend_from_slice(&2u32.to_le_bytes());
+    wtns.extend_from_slice(&((witness.len() * FIELD_SIZE) as u64).to_le_bytes());
+    for value in witness {
+        wtns.extend_from_slice(&field_bytes(value));
+    }
+    wtns
+}
+
+/// Calculates the witness of a mocked circuit. Used by [`crate::calculate_witness`].
+pub(crate) fn calculate(circuit_name: &str, json_input: &str) -> Result<Vec<u8>> {
+    let witness_fn = mocks()
+        .read()
+        .unwrap()
+        .get(circuit_name)
+        .cloned()
+        .ok_or_else(|| {
+            anyhow!(
+                "No mock witness for circuit {}, set one with mock::set_witness",
+                circuit_name
+            )
+        })?;
+    Ok(encode_wtns(&witness_fn(json_input)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{parse_witness_to_bigints, CircuitData, WitnessCalculator};
+
+    crate::witness!(multiplier2);
+    crate::witness!(sha256, dat = runtime);
+
+    fn prime() -> BigInt {
+        BN254_PRIME.parse().unwrap()
+    }
+
+    #[test]
+    fn test_encode_wtns() {
+        let cases = [
+            (vec![], vec![]),
+            (
+                vec![BigInt::from(1u8), BigInt::from(6u8)],
+                vec![BigInt::from(1u8), BigInt::from(6u8)],
+            ),
+            // Reduced into the field
+            (vec![BigInt::from(-1)], vec![prime() - 1u8]),
+            (vec![-prime() - 2u8], vec![prime() - 2u8]),
+            (vec![prime()], vec![BigInt::from(0u8)]),
+            (vec![prime() * 2u8 + 5u8], vec![BigInt::from(5u8)]),
+        ];
+        for (witness, expected) in cases {
+            let wtns = encode_wtns(&witness);
+            assert_eq!(parse_witness_to_bigints(&wtns).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_set_witness() {
+        set_witness("multiplier2", vec![BigInt::from(1u8), BigInt::from(6u8)]);
+        let witness = parse_witness_to_bigints(&multiplier2_witness("{}").unwrap()).unwrap();
+        assert_eq!(witness, vec![BigInt::from(1u8), BigInt::from(6u8)]);
+        assert_eq!(
+            Multiplier2Circuit::calculate_bigints("{}").unwrap(),
+            witness
+        );
+
+        clear_witness("multiplier2");
+        assert!(multiplier2_witness("{}").is_err());
+    }
+
+    #[test]
+    fn test_set_witness_fn() {
+        set_witness_fn("sha256", |json_input| {
+            let inputs: serde_json::Value = serde_json::from_str(json_input)?;
+            let a = inputs["a"]
+                .as_i64()
+                .ok_or_else(|| anyhow!("a is missing"))?;
+            Ok(vec![BigInt::from(1u8), BigInt::from(a * 2)])
+        });
+        // Circuits with their data provided at runtime still need it loaded
+        assert!(sha256_witness("{\"a\": 4}").is_err());
+        let circuit_data = CircuitData::from_bytes(vec![0]);
+        let wtns = sha256_witness_with_dat(&circuit_data, "{\"a\": 4}").unwrap();
+        sha256_load_dat(circuit_data).unwrap();
+        assert_eq!(sha256_witness("{\"a\": 4}").unwrap(), wtns);
+        let witness = parse_witness_to_bigints(&wtns).unwrap();
+        assert_eq!(witness, vec![BigInt::from(1u8), BigInt::from(8u8)]);
+        assert!(sha256_witness("{}").is_err());
+
+        clear_witness("sha256");
+        assert!(sha256_witness("{\"a\": 4}").is_err());
+    }
+}
@@ -0,0 +1,57 @@
+use std::{collections::BTreeMap, sync::OnceLock};
+
+use anyhow::{anyhow, Result};
+
+use crate::Circuit;
+
+inventory::collect!(Circuit);
+
+/// Every circuit declared with [`crate::witness`] in the final binary, looked up by name.
+#[derive(Debug)]
+pub struct Registry {
+    circuits: BTreeMap<&'static str, &'static Circuit>,
+}
+
+impl Registry {
+    /// Looks up a circuit by the name passed to [`crate::witness`].
+    pub fn get(&self, name: &str) -> Result<&'static Circuit> {
+        self.circuits
+            .get(name)
+            .copied()
+            .ok_or_else(|| anyhow!("Unknown circuit: {}", name))
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.circuits.contains_key(name)
+    }
+
+    /// Names of the registered circuits, in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &'static str> + '_ {
+        self.circuits.keys().copied()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &'static Circuit> + '_ {
+        self.circuits.values().copied()
+    }
+}
+
+/// Returns the registry of all circuits declared with [`crate::witness`].
+///
+/// A circuit declared several times (e.g. with different `.dat` modes in different modules)
+/// is registered once, under the declaration whose `.dat` file is readiest to use: embedded,
+/// then compressed, then loaded at runtime (see [`crate::DatSource`]). Declarations with the
+/// same `.dat` source calculate the same witnesses.
+pub fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut circuits = BTreeMap::<&'static str, &'static Circuit>::new();
+        for circuit in inventory::iter::<Circuit> {
+            let registered = circuits.entry(circuit.name()).or_insert(circuit);
+            // inventory does not guarantee an iteration order
+            if circuit.dat_source() < registered.dat_source() {
+                *registered = circuit;
+            }
+        }
+        Registry { circuits }
+    })
+}